@@ -0,0 +1,121 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::error::AmountParseError;
+
+/// Number of decimal places an `Amount` is precise to.
+pub const AMOUNT_PRECISION: usize = 4;
+
+// number of raw units in a whole unit of currency
+const SCALE: i64 = 10_i64.pow(AMOUNT_PRECISION as u32);
+
+/// A fixed-point decimal amount with four decimal places of precision.
+///
+/// Amounts are stored as an integer number of ten-thousandths, so adding and subtracting them never accumulates rounding errors.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(i64);
+
+impl Amount {
+    pub const ZERO: Self = Self(0);
+
+    /// Creates an amount from a raw number of ten-thousandths.
+    pub const fn from_raw(raw: i64) -> Self {
+        Self(raw)
+    }
+
+    /// Returns the raw number of ten-thousandths in this amount.
+    pub const fn raw(self) -> i64 {
+        self.0
+    }
+
+    /// Returns `None` if the result would overflow.
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        self.0.checked_add(rhs.0).map(Self)
+    }
+
+    /// Returns `None` if the result would overflow.
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        self.0.checked_sub(rhs.0).map(Self)
+    }
+
+    pub fn saturating_add(self, rhs: Self) -> Self {
+        Self(self.0.saturating_add(rhs.0))
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+}
+
+impl FromStr for Amount {
+    type Err = AmountParseError;
+
+    /// Parses a decimal amount such as `12`, `-3.5` or `0.0001`.
+    ///
+    /// Fractional digits past the fourth are only accepted if they are zeros, as anything else could not be represented exactly.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+
+        let (int_part, frac_part) = digits.split_once('.').unwrap_or((digits, ""));
+
+        if int_part.is_empty() && frac_part.is_empty() {
+            return Err(AmountParseError::Empty);
+        }
+
+        if !int_part
+            .bytes()
+            .chain(frac_part.bytes())
+            .all(|b| b.is_ascii_digit())
+        {
+            return Err(AmountParseError::InvalidDigit);
+        }
+
+        if frac_part.bytes().skip(AMOUNT_PRECISION).any(|b| b != b'0') {
+            return Err(AmountParseError::TooPrecise);
+        }
+
+        // parse the whole units
+        let mut raw: i64 = 0;
+        for b in int_part.bytes() {
+            raw = raw
+                .checked_mul(10)
+                .and_then(|x| x.checked_add(i64::from(b - b'0')))
+                .ok_or(AmountParseError::Overflow)?;
+        }
+        raw = raw.checked_mul(SCALE).ok_or(AmountParseError::Overflow)?;
+
+        // parse the fractional units, padding with zeros to the full precision
+        let mut frac: i64 = 0;
+        for i in 0..AMOUNT_PRECISION {
+            let digit = frac_part.as_bytes().get(i).map_or(0, |b| b - b'0');
+            frac = frac * 10 + i64::from(digit);
+        }
+        raw = raw.checked_add(frac).ok_or(AmountParseError::Overflow)?;
+
+        Ok(Self(if negative { -raw } else { raw }))
+    }
+}
+
+impl Display for Amount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.is_negative() { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let scale = SCALE.unsigned_abs();
+
+        let args = format_args!(
+            "{sign}{}.{:0width$}",
+            abs / scale,
+            abs % scale,
+            width = AMOUNT_PRECISION
+        );
+
+        // only format into a string first if it needs to be padded to a width
+        match f.width() {
+            Some(_) => f.pad(&args.to_string()),
+            None => f.write_fmt(args),
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::{fmt::Display, num::NonZeroUsize};
+
+use payments_engine::{AccountOrder, ClientId, Mode, OutputFormat};
+
+pub const HELP: &str = "\
+Usage: payments-engine [COMMAND] [OPTIONS] <FILES>...
+       payments-engine serve [--listen <ADDR>] [--http <ADDR>] [OPTIONS]
+
+Runs the payment commands in each CSV file in order, and prints the resulting accounts.
+A file named - is read from standard input.
+
+Commands:
+  process    Run the commands and print the accounts (default)
+  validate   Parse the files without running any commands
+  inspect    Run the commands and print the history of a single client
+  serve      Run the commands sent over TCP or HTTP into shared accounts
+
+Options:
+  -o, --output <FILE>      Write the output to FILE instead of standard output [process, inspect]
+  -f, --format <FORMAT>    Format of the accounts: csv, jsonl, table or fixed [process, serve]
+      --order <ORDER>      Order of the accounts: client (default), total or insertion [process, serve]
+      --rejections <FILE>  Write a CSV of every ignored or skipped row, and why, to FILE [process]
+      --restore <FILE>     Start from the state saved in the snapshot FILE [process, serve]
+      --snapshot <FILE>    Save the state to the snapshot FILE once every file is run, emptying the journal [process]
+      --journal <FILE>     Append every command to the journal FILE before running it, replaying it first [process]
+      --threads <N>        Run the clients across N threads, with the same output [process]
+      --client <ID>        Client whose history to print [inspect]
+      --listen <ADDR>      Address to accept CSV streams on, such as 127.0.0.1:7878 [serve]
+      --http <ADDR>        Address to serve the HTTP API on, such as 127.0.0.1:8080 [serve]
+      --strict             Stop at the first row that cannot be parsed or run (default)
+      --lenient            Skip rows that cannot be parsed or run, printing them to standard error
+  -h, --help               Print this help
+  -V, --version            Print the version
+
+Exit codes:
+  0  Success, including lenient runs that skipped rows
+  1  IO or other error
+  2  Invalid command line arguments
+  3  A row could not be parsed
+  4  A command could not be run
+  5  The snapshot could not be read
+  6  The journal could not be replayed
+";
+
+pub const EXIT_OTHER: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_PARSE: i32 = 3;
+pub const EXIT_RUNTIME: i32 = 4;
+pub const EXIT_SNAPSHOT: i32 = 5;
+pub const EXIT_JOURNAL: i32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subcommand {
+    Process,
+    Validate,
+    Inspect,
+    Serve,
+}
+
+impl Display for Subcommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Subcommand::Process => f.write_str("process"),
+            Subcommand::Validate => f.write_str("validate"),
+            Subcommand::Inspect => f.write_str("inspect"),
+            Subcommand::Serve => f.write_str("serve"),
+        }
+    }
+}
+
+/// Options shared by every subcommand, some of which only apply to some of them.
+#[derive(Debug, Default)]
+pub struct Options {
+    pub files: Vec<String>,
+    pub output: Option<String>,
+    pub format: OutputFormat,
+    pub mode: Mode,
+    pub order: AccountOrder,
+    pub rejections: Option<String>,
+    pub restore: Option<String>,
+    pub snapshot: Option<String>,
+    pub journal: Option<String>,
+    pub threads: Option<NonZeroUsize>,
+    pub listen: Option<String>,
+    pub http: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Process(Options),
+    Validate(Options),
+    Inspect(ClientId, Options),
+    Serve(Options),
+    Help,
+    Version,
+}
+
+// takes the value of an option, failing if there is none
+fn value(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("Please specify a value for {option}."))
+}
+
+/// Parses the command line arguments, without the name of the program.
+///
+/// The subcommand may be omitted, in which case it is `process`.
+///
+/// # Errors
+///
+/// Will return a message describing the first invalid argument.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut args = args.into_iter().peekable();
+
+    let subcommand = match args.peek().map(String::as_str) {
+        Some("process") => Some(Subcommand::Process),
+        Some("validate") => Some(Subcommand::Validate),
+        Some("inspect") => Some(Subcommand::Inspect),
+        Some("serve") => Some(Subcommand::Serve),
+        _ => None,
+    };
+
+    // anything else is the first option or file of an implicit process
+    if subcommand.is_some() {
+        args.next();
+    }
+
+    let subcommand = subcommand.unwrap_or(Subcommand::Process);
+
+    let mut options = Options::default();
+    let mut client = None;
+
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), subcommand) {
+            ("-h" | "--help", _) => return Ok(Command::Help),
+            ("-V" | "--version", _) => return Ok(Command::Version),
+            ("--strict", _) => options.mode = Mode::Strict,
+            ("--lenient", _) => options.mode = Mode::Lenient,
+            ("-o" | "--output", Subcommand::Process | Subcommand::Inspect) => {
+                options.output = Some(value(&mut args, &arg)?);
+            }
+            ("-f" | "--format", Subcommand::Process | Subcommand::Serve) => {
+                options.format = value(&mut args, &arg)?.parse()?;
+            }
+            ("--order", Subcommand::Process | Subcommand::Serve) => {
+                options.order = value(&mut args, &arg)?.parse()?;
+            }
+            ("--rejections", Subcommand::Process) => {
+                options.rejections = Some(value(&mut args, &arg)?);
+            }
+            ("--restore", Subcommand::Process | Subcommand::Serve) => {
+                options.restore = Some(value(&mut args, &arg)?);
+            }
+            ("--snapshot", Subcommand::Process) => {
+                options.snapshot = Some(value(&mut args, &arg)?);
+            }
+            ("--journal", Subcommand::Process) => {
+                options.journal = Some(value(&mut args, &arg)?);
+            }
+            ("--threads", Subcommand::Process) => {
+                let threads = value(&mut args, &arg)?;
+                options.threads = Some(
+                    threads
+                        .parse::<NonZeroUsize>()
+                        .map_err(|e| format!("Invalid number of threads {threads}: {e}"))?,
+                );
+            }
+            ("--listen", Subcommand::Serve) => options.listen = Some(value(&mut args, &arg)?),
+            ("--http", Subcommand::Serve) => options.http = Some(value(&mut args, &arg)?),
+            ("--client", Subcommand::Inspect) => {
+                let id = value(&mut args, &arg)?;
+                client = Some(
+                    id.parse::<ClientId>()
+                        .map_err(|e| format!("Invalid client id {id}: {e}"))?,
+                );
+            }
+            ("-", _) => options.files.push(arg),
+            (_, _) if arg.starts_with('-') => {
+                return Err(format!("Unexpected argument for {subcommand}: {arg}"));
+            }
+            (_, _) => options.files.push(arg),
+        }
+    }
+
+    // a journal is appended to one command at a time, which the threads would have to wait on
+    if options.threads.is_some() && options.journal.is_some() {
+        return Err("--threads cannot be used with --journal.".to_string());
+    }
+
+    match subcommand {
+        // a server is sent its commands over connections rather than reading files
+        Subcommand::Serve => {
+            if let Some(file) = options.files.first() {
+                return Err(format!("Unexpected argument for serve: {file}"));
+            }
+
+            if options.listen.is_none() && options.http.is_none() {
+                return Err(
+                    "Please specify an address to serve on with --listen or --http.".to_string(),
+                );
+            }
+
+            Ok(Command::Serve(options))
+        }
+        _ if options.files.is_empty() => {
+            Err("Please specify a file to run, or - for standard input.".to_string())
+        }
+        Subcommand::Process => Ok(Command::Process(options)),
+        Subcommand::Validate => Ok(Command::Validate(options)),
+        Subcommand::Inspect => {
+            let client = client
+                .ok_or_else(|| "Please specify a client to inspect with --client.".to_string())?;
+
+            Ok(Command::Inspect(client, options))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|x| x.to_string()))
+    }
+
+    #[test]
+    fn subcommands() {
+        let Ok(Command::Process(options)) = parse(&["a.csv", "-", "--lenient", "--order", "total"])
+        else {
+            panic!("expected an implicit process");
+        };
+        assert_eq!(options.files, ["a.csv", "-"]);
+        assert_eq!(options.mode, Mode::Lenient);
+        assert_eq!(options.order, AccountOrder::Total);
+
+        assert!(matches!(
+            parse(&["inspect", "--client", "7", "a.csv"]),
+            Ok(Command::Inspect(7, _))
+        ));
+        assert!(matches!(
+            parse(&["validate", "a.csv"]),
+            Ok(Command::Validate(_))
+        ));
+        assert!(matches!(parse(&["process", "--help"]), Ok(Command::Help)));
+
+        // options only apply to the subcommands they make sense for
+        assert!(parse(&["validate", "-o", "out.csv", "a.csv"]).is_err());
+        assert!(parse(&["inspect", "a.csv"]).is_err());
+        assert!(matches!(
+            parse(&["-f", "jsonl", "a.csv"]),
+            Ok(Command::Process(Options {
+                format: OutputFormat::JsonLines,
+                ..
+            }))
+        ));
+        assert!(parse(&["-f", "xml", "a.csv"]).is_err());
+        assert!(parse(&["validate", "--journal", "j.log", "a.csv"]).is_err());
+        assert!(matches!(
+            parse(&["--threads", "4", "a.csv"]),
+            Ok(Command::Process(Options {
+                threads: Some(_),
+                ..
+            }))
+        ));
+        assert!(parse(&["--threads", "0", "a.csv"]).is_err());
+        assert!(parse(&["--threads", "4", "--journal", "j.log", "a.csv"]).is_err());
+        assert!(matches!(
+            parse(&["serve", "--listen", "127.0.0.1:0", "--order", "total"]),
+            Ok(Command::Serve(Options {
+                order: AccountOrder::Total,
+                listen: Some(_),
+                http: None,
+                ..
+            }))
+        ));
+        assert!(parse(&["serve", "a.csv", "--listen", "127.0.0.1:0"]).is_err());
+        assert!(matches!(
+            parse(&["serve", "--http", "127.0.0.1:0"]),
+            Ok(Command::Serve(Options { http: Some(_), .. }))
+        ));
+        assert!(parse(&["serve"]).is_err());
+        assert!(parse(&["serve", "--listen", "127.0.0.1:0", "--snapshot", "s.snap"]).is_err());
+        assert!(parse(&["--output"]).is_err());
+        assert!(parse(&[]).is_err());
+    }
+}
@@ -1,19 +1,41 @@
-pub type ClientId = u16;
-pub type TransactionId = u16;
+use std::{fmt::Display, sync::Arc};
+
+pub use crate::amount::Amount;
+
+pub type ClientId = u32;
+pub type TransactionId = u64;
+
+/// Where a command was read from: the file, if known, the line its record starts on, and the byte offset of that line.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Location {
+    pub file: Option<Arc<str>>,
+    pub line: usize,
+    pub offset: u64,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            f.write_fmt(format_args!("{}, ", file))?;
+        }
+
+        f.write_fmt(format_args!("line {}, byte {}", self.line, self.offset))
+    }
+}
 
 // represents a single payment engine action
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PaymentCommand {
     Deposit {
         client: ClientId,
         tx: TransactionId,
-        amount: f64,
+        amount: Amount,
     },
 
     Withdrawal {
         client: ClientId,
         tx: TransactionId,
-        amount: f64,
+        amount: Amount,
     },
 
     Dispute {
@@ -31,3 +53,46 @@ pub enum PaymentCommand {
         tx: TransactionId,
     },
 }
+
+impl PaymentCommand {
+    /// Returns the name of the command, as written in the `type` column.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PaymentCommand::Deposit { .. } => "deposit",
+            PaymentCommand::Withdrawal { .. } => "withdrawal",
+            PaymentCommand::Dispute { .. } => "dispute",
+            PaymentCommand::Resolve { .. } => "resolve",
+            PaymentCommand::Chargeback { .. } => "chargeback",
+        }
+    }
+
+    /// Returns the amount of a deposit or withdrawal, or `None` for any other command.
+    pub fn amount(&self) -> Option<Amount> {
+        match *self {
+            PaymentCommand::Deposit { amount, .. } | PaymentCommand::Withdrawal { amount, .. } => {
+                Some(amount)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn client(&self) -> ClientId {
+        match *self {
+            PaymentCommand::Deposit { client, .. }
+            | PaymentCommand::Withdrawal { client, .. }
+            | PaymentCommand::Dispute { client, .. }
+            | PaymentCommand::Resolve { client, .. }
+            | PaymentCommand::Chargeback { client, .. } => client,
+        }
+    }
+
+    pub fn tx(&self) -> TransactionId {
+        match *self {
+            PaymentCommand::Deposit { tx, .. }
+            | PaymentCommand::Withdrawal { tx, .. }
+            | PaymentCommand::Dispute { tx, .. }
+            | PaymentCommand::Resolve { tx, .. }
+            | PaymentCommand::Chargeback { tx, .. } => tx,
+        }
+    }
+}
@@ -1,17 +1,111 @@
-use std::{collections::HashMap, fmt::Display};
-
-use crate::{common::*, error::*, parse::parse_commands};
-
-#[derive(Debug, Default)]
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs::File,
+    io::{BufRead, BufReader},
+};
+
+use crate::{
+    common::*,
+    error::*,
+    journal::Journal,
+    output::{AccountOrder, AccountWriter},
+    parse::{AmountLimits, CommandReader},
+    store::{AccountStore, TransactionStore},
+    transaction::{
+        RedisputePolicy, TransactionEvent, TransactionKind, TransactionStatus, TransitionPolicy,
+    },
+};
+
+#[derive(Debug, Default, Clone, Copy)]
 pub struct ClientData {
-    available: f64,
-    held: f64,
+    available: Amount,
+    held: Amount,
     locked: bool,
 }
 
 impl ClientData {
-    pub fn total(&self) -> f64 {
-        self.held + self.available
+    pub fn available(&self) -> Amount {
+        self.available
+    }
+
+    pub fn held(&self) -> Amount {
+        self.held
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    pub(crate) fn new(available: Amount, held: Amount, locked: bool) -> Self {
+        Self {
+            available,
+            held,
+            locked,
+        }
+    }
+
+    // the engine never lets the total overflow, so saturating is only a safeguard
+    pub fn total(&self) -> Amount {
+        self.held.saturating_add(self.available)
+    }
+
+    /// Adds `amount` to the available funds.
+    ///
+    /// Returns `None`, leaving the client untouched, if either the available or total funds would overflow.
+    fn credit(&mut self, amount: Amount) -> Option<()> {
+        let available = self.available.checked_add(amount)?;
+        available.checked_add(self.held)?;
+
+        self.available = available;
+
+        Some(())
+    }
+
+    /// Removes `amount` from the available funds.
+    fn debit(&mut self, amount: Amount) -> Option<()> {
+        self.available = self.available.checked_sub(amount)?;
+
+        Some(())
+    }
+
+    /// Adds `amount` to the held funds, checking that neither the held nor the total funds overflow.
+    fn hold_credit(&mut self, amount: Amount) -> Option<()> {
+        let held = self.held.checked_add(amount)?;
+        held.checked_add(self.available)?;
+
+        self.held = held;
+
+        Some(())
+    }
+
+    /// Moves `amount` from the available funds into the held funds.
+    fn hold(&mut self, amount: Amount) -> Option<()> {
+        let available = self.available.checked_sub(amount)?;
+        let held = self.held.checked_add(amount)?;
+
+        self.available = available;
+        self.held = held;
+
+        Some(())
+    }
+
+    /// Moves `amount` from the held funds back into the available funds.
+    fn release(&mut self, amount: Amount) -> Option<()> {
+        let available = self.available.checked_add(amount)?;
+        let held = self.held.checked_sub(amount)?;
+
+        self.available = available;
+        self.held = held;
+
+        Some(())
+    }
+
+    /// Removes `amount` from the held funds.
+    fn remove_held(&mut self, amount: Amount) -> Option<()> {
+        self.held = self.held.checked_sub(amount)?;
+
+        Some(())
     }
 
     pub fn to_csv_string(&self, id: ClientId) -> String {
@@ -25,25 +119,182 @@ impl ClientData {
     }
 }
 
+/// How a run reacts to rows that cannot be parsed or commands that fail.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Stop at the first error.
+    #[default]
+    Strict,
+    /// Skip the offending row, collect the error, and keep going.
+    Lenient,
+}
+
+/// The result of successfully running a single `PaymentCommand`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum TransactionStatus {
-    Ok,
-    Disputed,
-    Resolved,
-    Chargeback,
+pub enum CommandOutcome {
+    Applied,
+    Ignored(IgnoreReason),
 }
 
-#[derive(Debug)]
+/// The reason a `PaymentCommand` was ignored without changing any account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IgnoreReason {
+    InsufficientFunds,
+    UnknownTransaction,
+    AlreadyDisputed,
+    NotDisputed,
+    /// The transaction was resolved, charged back or declined, and cannot be disputed again.
+    NotDisputable,
+}
+
+impl Display for CommandOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandOutcome::Applied => f.write_str("applied"),
+            CommandOutcome::Ignored(reason) => f.write_fmt(format_args!("ignored: {}", reason)),
+        }
+    }
+}
+
+impl Display for IgnoreReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IgnoreReason::InsufficientFunds => f.write_str("insufficient funds"),
+            IgnoreReason::UnknownTransaction => f.write_str("unknown transaction"),
+            IgnoreReason::AlreadyDisputed => f.write_str("transaction already disputed"),
+            IgnoreReason::NotDisputed => f.write_str("transaction not disputed"),
+            IgnoreReason::NotDisputable => f.write_str("transaction cannot be disputed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct TransactionData {
     client: ClientId,
-    amount: f64,
+    kind: TransactionKind,
+    amount: Amount,
     status: TransactionStatus,
 }
 
+impl TransactionData {
+    pub(crate) fn new(
+        client: ClientId,
+        kind: TransactionKind,
+        amount: Amount,
+        status: TransactionStatus,
+    ) -> Self {
+        Self {
+            client,
+            kind,
+            amount,
+            status,
+        }
+    }
+
+    pub fn client(&self) -> ClientId {
+        self.client
+    }
+
+    pub fn kind(&self) -> TransactionKind {
+        self.kind
+    }
+
+    pub fn amount(&self) -> Amount {
+        self.amount
+    }
+
+    pub fn status(&self) -> TransactionStatus {
+        self.status
+    }
+}
+
+/// Settings that change how the engine treats certain commands.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EngineConfig {
+    pub redisputes: RedisputePolicy,
+    pub withdrawal_disputes: WithdrawalDisputePolicy,
+    pub invalid_transitions: TransitionPolicy,
+    /// Limits on the amount of each deposit and withdrawal, checked as commands are run, and as they are read by `PaymentsEngine::run_from_reader`.
+    pub amount_limits: AmountLimits,
+}
+
+/// How disputes of withdrawals are handled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum WithdrawalDisputePolicy {
+    /// Disputing a withdrawal fails with `EngineError::DisputedWithdrawal`.
+    #[default]
+    Reject,
+    /// The withdrawn amount is held as a pending credit while disputed.
+    /// A resolve lets the withdrawal stand and drops the credit, while a chargeback reverses the withdrawal, returning the funds to the client.
+    ReverseFlow,
+}
+
+/// The effect of a single command on the engine, worked out without changing it.
+///
+/// At most one account and one transaction are changed by any command.
+#[derive(Debug)]
+pub(crate) struct Change {
+    client: Option<(ClientId, ClientData)>,
+    transaction: Option<(TransactionId, TransactionData)>,
+    result: Result<CommandOutcome, EngineError>,
+}
+
+impl Default for Change {
+    fn default() -> Self {
+        Self {
+            client: None,
+            transaction: None,
+            result: Ok(CommandOutcome::Applied),
+        }
+    }
+}
+
+impl Change {
+    pub(crate) fn result(&self) -> &Result<CommandOutcome, EngineError> {
+        &self.result
+    }
+
+    // get the changed account, starting from its current state in the engine, or a new account
+    fn client_mut<A: AccountStore, T: TransactionStore>(
+        &mut self,
+        engine: &PaymentsEngine<A, T>,
+        client: ClientId,
+    ) -> Result<&mut ClientData, EngineError> {
+        let data = match self.client {
+            Some((_, data)) => data,
+            None => engine.clients.get(client)?.unwrap_or_default(),
+        };
+        let (_, data) = self.client.insert((client, data));
+
+        Ok(data)
+    }
+
+    // declined transactions are still recorded, so that replaying one later cannot succeed
+    fn decline(&mut self, tx: TransactionId, mut transaction: TransactionData) {
+        transaction.status = TransactionStatus::Declined;
+        self.transaction = Some((tx, transaction));
+    }
+}
+
+/// The payments engine, keeping its accounts in an `AccountStore` and its transactions in a `TransactionStore`.
+///
+/// By default, both are kept in a `HashMap`.
 #[derive(Debug, Default)]
-pub struct PaymentsEngine {
-    clients: HashMap<ClientId, ClientData>,
-    transactions: HashMap<TransactionId, TransactionData>,
+pub struct PaymentsEngine<
+    A = HashMap<ClientId, ClientData>,
+    T = HashMap<TransactionId, TransactionData>,
+> {
+    clients: A,
+    // ids of the accounts in the order they were created, for `AccountOrder::Insertion`
+    order: Vec<ClientId>,
+    // clients put back as an empty account after their first change failed to be stored, which are not in `order` until a later change is
+    unlisted: HashSet<ClientId>,
+    transactions: T,
+    config: EngineConfig,
+    // commands are appended here before they are committed, see `PaymentsEngine::open_journal`
+    pub(crate) journal: Option<Journal>,
+    // the number of commands ever journaled into the state of the engine, which snapshots record so a journal can be matched to them
+    pub(crate) journaled: u64,
 }
 
 impl PaymentsEngine {
@@ -53,234 +304,638 @@ impl PaymentsEngine {
         }
     }
 
-    // get or insert default
-    pub fn get_client_mut(&mut self, client: ClientId) -> &mut ClientData {
-        self.clients
-            .entry(client)
-            .or_insert_with(ClientData::default)
+    pub fn with_config(config: EngineConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Creates an engine with room for at least `clients` accounts and `transactions` transactions without reallocating.
+    ///
+    /// Large inputs can hold millions of transactions, so reserving up front avoids repeatedly rehashing the transaction map.
+    pub fn with_capacity(clients: usize, transactions: usize) -> Self {
+        Self {
+            clients: HashMap::with_capacity(clients),
+            order: Vec::with_capacity(clients),
+            transactions: HashMap::with_capacity(transactions),
+            ..Default::default()
+        }
+    }
+
+    // rebuilds an engine from its accounts, in the order they were created, and its transactions
+    pub(crate) fn from_parts(
+        config: EngineConfig,
+        accounts: Vec<(ClientId, ClientData)>,
+        transactions: HashMap<TransactionId, TransactionData>,
+    ) -> Self {
+        Self {
+            order: accounts.iter().map(|(id, _)| *id).collect(),
+            clients: accounts.into_iter().collect(),
+            unlisted: HashSet::new(),
+            transactions,
+            config,
+            journal: None,
+            journaled: 0,
+        }
+    }
+
+    // takes an engine apart into the accounts, in the order they were created, and the transactions it is built from by `from_parts`
+    pub(crate) fn into_parts(
+        mut self,
+    ) -> (
+        Vec<(ClientId, ClientData)>,
+        HashMap<TransactionId, TransactionData>,
+    ) {
+        let accounts = self
+            .order
+            .iter()
+            .filter_map(|id| Some((*id, self.clients.remove(id)?)))
+            .collect();
+
+        (accounts, self.transactions)
+    }
+}
+
+impl<A: AccountStore, T: TransactionStore> PaymentsEngine<A, T> {
+    /// Creates an engine keeping its accounts in `clients` and its transactions in `transactions`, which must both be empty.
+    pub fn with_stores(config: EngineConfig, clients: A, transactions: T) -> Self {
+        Self {
+            clients,
+            order: Vec::new(),
+            unlisted: HashSet::new(),
+            transactions,
+            config,
+            journal: None,
+            journaled: 0,
+        }
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Returns the account of `client`, if it has one.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from reading the account store.
+    pub fn get_account(&self, client: ClientId) -> Result<Option<ClientData>, Error> {
+        Ok(self.clients.get(client)?)
+    }
+
+    /// Iterates over every account, in the order they were created.
+    pub fn accounts(&self) -> impl Iterator<Item = Result<(ClientId, ClientData), Error>> + '_ {
+        self.order.iter().map(|id| {
+            let data = self.clients.get(*id)?.ok_or_else(|| {
+                Error::Other(format!(
+                    "The account of client {id} is missing from the store"
+                ))
+            })?;
+
+            Ok((*id, data))
+        })
+    }
+
+    /// Iterates over every deposit and withdrawal, including declined ones, in no particular order.
+    pub fn transactions(
+        &self,
+    ) -> impl Iterator<Item = Result<(TransactionId, TransactionData), Error>> + '_ {
+        self.transactions.iter().map(|x| Ok(x?))
+    }
+
+    /// Returns the deposit or withdrawal with id `tx`, if there was one.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from reading the transaction store.
+    pub fn get_transaction(&self, tx: TransactionId) -> Result<Option<TransactionData>, Error> {
+        Ok(self.transactions.get(tx)?)
+    }
+
+    /// Returns every account, in the given order.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from reading the account store.
+    pub fn accounts_in_order(
+        &self,
+        order: AccountOrder,
+    ) -> Result<Vec<(ClientId, ClientData)>, Error> {
+        let mut accounts = self.accounts().collect::<Result<Vec<_>, _>>()?;
+
+        match order {
+            AccountOrder::Total => accounts.sort_unstable_by(|(id1, data1), (id2, data2)| {
+                data2.total().cmp(&data1.total()).then(id1.cmp(id2))
+            }),
+            AccountOrder::ClientId => accounts.sort_unstable_by_key(|(id, _)| *id),
+            AccountOrder::Insertion => {}
+        }
+
+        Ok(accounts)
+    }
+
+    // transaction ids are unique, a later transaction must never overwrite the record of an earlier one
+    fn check_unused(&self, tx: TransactionId) -> Result<(), EngineError> {
+        if self.transactions.get(tx)?.is_some() {
+            return Err(EngineError::DuplicateTransaction(tx));
+        }
+
+        Ok(())
     }
 
-    /// Runs payment commands from a slice
+    /// Runs payment commands from a slice, without journaling them.
     ///
     /// # Errors
     ///
-    /// Will return any error from calling `PaymentsEngine::run_command`, wrapped in a `RuntimeError` with the correct line number.
+    /// Will return any error from calling `PaymentsEngine::run_command`, wrapped in a `RuntimeError`.
+    /// As the commands were not read from a file, the line of its location is the 1-based position of the command in the slice.
     pub fn run_commands(&mut self, commands: &[PaymentCommand]) -> Result<(), RuntimeError> {
         for (i, command) in commands.iter().enumerate() {
-            self.run_command(*command)
-                .map_err(|e| RuntimeError(i + 2, e))?;
+            self.run_command(*command).map_err(|e| {
+                let location = Location {
+                    line: i + 1,
+                    ..Default::default()
+                };
+
+                RuntimeError(location, e)
+            })?;
         }
 
         Ok(())
     }
 
-    /// Runs a payment command
+    /// Runs a payment command, returning whether it was applied or why it was ignored.
+    ///
+    /// The command is not journaled, see `PaymentsEngine::open_journal`.
     ///
     /// # Errors
     ///
     /// - `ClietIdMismatch`: if the client id in a Dispute, Resolve, or Chargeback command differs from the client id in the transaction it references.
-    pub fn run_command(&mut self, command: PaymentCommand) -> Result<(), EngineError> {
+    /// - `AmountOverflow`: if applying the command would overflow one of the client's balances.
+    /// - `DuplicateTransaction`: if a Deposit or Withdrawal uses the id of an earlier transaction, including one that was declined.
+    /// - `AccountLocked`: if a Deposit, Withdrawal, or Dispute is made on an account that has been locked by a chargeback.
+    /// - `DisputedWithdrawal`: if a Dispute references a withdrawal and `EngineConfig::withdrawal_disputes` is `WithdrawalDisputePolicy::Reject`.
+    /// - `InvalidTransition`: if a Dispute, Resolve, or Chargeback is not a legal transition from the transaction's status, see `TransactionStatus::transition`, and `EngineConfig::invalid_transitions` is `TransitionPolicy::Reject`. Otherwise the command is ignored.
+    /// - `InvalidAmount`: if the amount of a Deposit or Withdrawal is not positive, or is outside `EngineConfig::amount_limits`.
+    pub fn run_command(&mut self, command: PaymentCommand) -> Result<CommandOutcome, EngineError> {
+        let change = self.evaluate(command);
+
+        self.commit(change)
+    }
+
+    // works out the effect of a command without changing the engine, so it can be journaled before it is committed
+    pub(crate) fn evaluate(&self, command: PaymentCommand) -> Change {
+        let mut change = Change::default();
+
+        match self.evaluate_into(command, &mut change) {
+            // a store that could not be read leaves the change incomplete, so none of it is committed
+            Err(e @ EngineError::Storage(_)) => Change {
+                result: Err(e),
+                ..Default::default()
+            },
+            // only an applied command changes the account, while a refused one may still record its declined transaction
+            Ok(CommandOutcome::Applied) => Change {
+                result: Ok(CommandOutcome::Applied),
+                ..change
+            },
+            result => Change {
+                client: None,
+                result,
+                ..change
+            },
+        }
+    }
+
+    // applies a change worked out by `evaluate`, returning the result of the command
+    //
+    // a change is stored whole or not at all, so if the transaction cannot be stored, the account is put back as it was
+    pub(crate) fn commit(&mut self, change: Change) -> Result<CommandOutcome, EngineError> {
+        let previous = match change.client {
+            Some((client, data)) => Some((client, self.clients.insert(client, data)?)),
+            None => None,
+        };
+
+        if let Some((tx, data)) = change.transaction {
+            if let Err(e) = self.transactions.insert(tx, data) {
+                // a store cannot remove an account, but an empty one is the same as none, as long as it is not listed
+                if let Some((client, data)) = previous {
+                    if data.is_none() {
+                        self.unlisted.insert(client);
+                    }
+                    self.clients.insert(client, data.unwrap_or_default())?;
+                }
+
+                return Err(e.into());
+            }
+        }
+
+        if let Some((client, data)) = previous {
+            if data.is_none() || self.unlisted.remove(&client) {
+                self.order.push(client);
+            }
+        }
+
+        change.result
+    }
+
+    // an illegal transition is only an error if the config asks for one, as partners routinely repeat a dispute or resolve it twice
+    fn refuse_transition(&self, error: EngineError) -> Result<CommandOutcome, EngineError> {
+        let EngineError::InvalidTransition(from, to) = error else {
+            return Err(error);
+        };
+
+        if self.config.invalid_transitions == TransitionPolicy::Reject {
+            return Err(error);
+        }
+
+        Ok(CommandOutcome::Ignored(match (from, to) {
+            (TransactionStatus::Disputed, TransactionStatus::Disputed) => {
+                IgnoreReason::AlreadyDisputed
+            }
+            (_, TransactionStatus::Disputed) => IgnoreReason::NotDisputable,
+            _ => IgnoreReason::NotDisputed,
+        }))
+    }
+
+    // a declined transaction recorded before an error is returned is still committed, see `evaluate`
+    fn evaluate_into(
+        &self,
+        command: PaymentCommand,
+        change: &mut Change,
+    ) -> Result<CommandOutcome, EngineError> {
+        // commands built by hand were never read, so their amounts are checked here as well
+        self.config
+            .amount_limits
+            .check(&command)
+            .map_err(EngineError::InvalidAmount)?;
+
         match command {
             PaymentCommand::Deposit { client, tx, amount } => {
+                self.check_unused(tx)?;
+
                 // create transaction struct
                 let transaction = TransactionData {
                     client,
+                    kind: TransactionKind::Deposit,
                     amount,
                     status: TransactionStatus::Ok,
                 };
 
-                // add transaction
-                self.transactions.insert(tx, transaction);
+                let client_data = change.client_mut(self, client)?;
 
-                // update client
-                let client_data = self.get_client_mut(client);
+                // a locked account cannot receive deposits
+                if client_data.locked {
+                    change.decline(tx, transaction);
+                    return Err(EngineError::AccountLocked(client));
+                }
 
                 // increase available funds
-                client_data.available += amount;
+                if client_data.credit(amount).is_none() {
+                    change.decline(tx, transaction);
+                    return Err(EngineError::AmountOverflow(client));
+                }
+
+                // add transaction
+                change.transaction = Some((tx, transaction));
             }
             PaymentCommand::Withdrawal { client, tx, amount } => {
-                // fail silently if account is locked not enough funds
-                // it is ambiguous whether a withdrawal can occur to a frozen account, I have decided to assume it cannot.
-                if self
-                    .clients
-                    .get(&client)
-                    .map_or(true, |x| !x.locked && x.available < amount)
-                {
-                    return Ok(());
-                }
+                self.check_unused(tx)?;
 
                 // create transaction struct
                 let transaction = TransactionData {
                     client,
-                    amount: -amount,
+                    kind: TransactionKind::Withdrawal,
+                    amount,
                     status: TransactionStatus::Ok,
                 };
 
-                // add transaction
-                self.transactions.insert(tx, transaction);
+                let client_data = self.clients.get(client)?;
+
+                // a locked account cannot withdraw
+                if client_data.is_some_and(|x| x.locked) {
+                    change.decline(tx, transaction);
+                    return Err(EngineError::AccountLocked(client));
+                }
 
-                // update client
-                let client_data = self.get_client_mut(client);
+                // decline silently if there are not enough funds
+                if client_data.is_none_or(|x| x.available < amount) {
+                    change.decline(tx, transaction);
+                    return Ok(CommandOutcome::Ignored(IgnoreReason::InsufficientFunds));
+                }
 
                 // decrease available funds
-                client_data.available -= amount;
+                change
+                    .client_mut(self, client)?
+                    .debit(amount)
+                    .ok_or(EngineError::AmountOverflow(client))?;
+
+                // add transaction
+                change.transaction = Some((tx, transaction));
             }
             PaymentCommand::Dispute { client, tx } => {
-                match self.transactions.get_mut(&tx) {
-                    Some(transaction_data)
-                        if transaction_data.status != TransactionStatus::Disputed =>
-                    {
-                        if transaction_data.client != client {
-                            return Err(EngineError::ClientIdMismatch(
-                                client,
-                                transaction_data.client,
-                            ));
-                        }
-
-                        let amount = transaction_data.amount;
-
-                        // change transaction status
-                        transaction_data.status = TransactionStatus::Disputed;
-
-                        let client_data = self.get_client_mut(client);
-
-                        // update client funds
-                        client_data.available -= amount;
-                        client_data.held += amount;
-                    }
-                    // transaction does not exist or transaction already was disputed, fail silently
-                    _ => (),
+                // transaction does not exist, fail silently
+                let Some(transaction_data) = self.transactions.get(tx)? else {
+                    return Ok(CommandOutcome::Ignored(IgnoreReason::UnknownTransaction));
+                };
+
+                if transaction_data.client != client {
+                    return Err(EngineError::ClientIdMismatch(
+                        client,
+                        transaction_data.client,
+                    ));
+                }
+
+                let client_data = change.client_mut(self, client)?;
+
+                // a locked account cannot open new disputes
+                if client_data.locked {
+                    return Err(EngineError::AccountLocked(client));
+                }
+
+                if transaction_data.kind == TransactionKind::Withdrawal
+                    && self.config.withdrawal_disputes == WithdrawalDisputePolicy::Reject
+                {
+                    return Err(EngineError::DisputedWithdrawal(tx));
                 }
+
+                let status = match transaction_data
+                    .status
+                    .transition(TransactionEvent::Dispute, self.config.redisputes)
+                {
+                    Ok(status) => status,
+                    Err(e) => return self.refuse_transition(e),
+                };
+
+                // update client funds, a disputed withdrawal is held as a pending credit
+                match transaction_data.kind {
+                    TransactionKind::Deposit => client_data.hold(transaction_data.amount),
+                    TransactionKind::Withdrawal => client_data.hold_credit(transaction_data.amount),
+                }
+                .ok_or(EngineError::AmountOverflow(client))?;
+
+                // change transaction status
+                change.transaction = Some((
+                    tx,
+                    TransactionData {
+                        status,
+                        ..transaction_data
+                    },
+                ));
             }
             PaymentCommand::Resolve { client, tx } => {
-                match self.transactions.get_mut(&tx) {
-                    Some(transaction_data)
-                        if transaction_data.status == TransactionStatus::Disputed =>
-                    {
-                        if transaction_data.client != client {
-                            return Err(EngineError::ClientIdMismatch(
-                                client,
-                                transaction_data.client,
-                            ));
-                        }
-
-                        let amount = transaction_data.amount;
-
-                        // change transaction status
-                        transaction_data.status = TransactionStatus::Resolved;
-
-                        let client_data = self.get_client_mut(client);
-
-                        // update client funds
-                        client_data.available += amount;
-                        client_data.held -= amount;
-                    }
-                    // transaction does not exist or transaction is not disputed, fail silently
-                    _ => (),
+                // transaction does not exist, fail silently
+                let Some(transaction_data) = self.transactions.get(tx)? else {
+                    return Ok(CommandOutcome::Ignored(IgnoreReason::UnknownTransaction));
+                };
+
+                if transaction_data.client != client {
+                    return Err(EngineError::ClientIdMismatch(
+                        client,
+                        transaction_data.client,
+                    ));
+                }
+
+                let status = match transaction_data
+                    .status
+                    .transition(TransactionEvent::Resolve, self.config.redisputes)
+                {
+                    Ok(status) => status,
+                    Err(e) => return self.refuse_transition(e),
+                };
+
+                let client_data = change.client_mut(self, client)?;
+
+                // update client funds, a resolved withdrawal stands so its pending credit is dropped
+                match transaction_data.kind {
+                    TransactionKind::Deposit => client_data.release(transaction_data.amount),
+                    TransactionKind::Withdrawal => client_data.remove_held(transaction_data.amount),
                 }
+                .ok_or(EngineError::AmountOverflow(client))?;
+
+                // change transaction status
+                change.transaction = Some((
+                    tx,
+                    TransactionData {
+                        status,
+                        ..transaction_data
+                    },
+                ));
             }
             PaymentCommand::Chargeback { client, tx } => {
-                match self.transactions.get_mut(&tx) {
-                    Some(transaction_data)
-                        if transaction_data.status == TransactionStatus::Disputed =>
-                    {
-                        if transaction_data.client != client {
-                            return Err(EngineError::ClientIdMismatch(
-                                client,
-                                transaction_data.client,
-                            ));
-                        }
-
-                        let amount = transaction_data.amount;
-
-                        // change transaction status
-                        transaction_data.status = TransactionStatus::Chargeback;
-
-                        let client_data = self.get_client_mut(client);
-
-                        // update client funds
-                        client_data.held -= amount;
-                        // freeze client
-                        client_data.locked = true;
-                    }
-                    // transaction does not exist or transaction is not disputed, fail silently
-                    _ => (),
+                // transaction does not exist, fail silently
+                let Some(transaction_data) = self.transactions.get(tx)? else {
+                    return Ok(CommandOutcome::Ignored(IgnoreReason::UnknownTransaction));
+                };
+
+                if transaction_data.client != client {
+                    return Err(EngineError::ClientIdMismatch(
+                        client,
+                        transaction_data.client,
+                    ));
+                }
+
+                let status = match transaction_data
+                    .status
+                    .transition(TransactionEvent::Chargeback, self.config.redisputes)
+                {
+                    Ok(status) => status,
+                    Err(e) => return self.refuse_transition(e),
+                };
+
+                let client_data = change.client_mut(self, client)?;
+
+                // update client funds, a charged back withdrawal is reversed so its pending credit is paid out
+                match transaction_data.kind {
+                    TransactionKind::Deposit => client_data.remove_held(transaction_data.amount),
+                    TransactionKind::Withdrawal => client_data.release(transaction_data.amount),
                 }
+                .ok_or(EngineError::AmountOverflow(client))?;
+                // freeze client
+                client_data.locked = true;
+
+                // change transaction status
+                change.transaction = Some((
+                    tx,
+                    TransactionData {
+                        status,
+                        ..transaction_data
+                    },
+                ));
             }
         }
 
-        Ok(())
+        Ok(CommandOutcome::Applied)
     }
 
-    /// Runs payment commands from a file
+    /// Runs payment commands streamed from a CSV reader, running each command as soon as it is parsed.
     ///
     /// # Errors
     ///
-    /// Will return any errors it encounters from `parse_commands` and `PaymentsEngine::run_commands` or an IO Error from `std::fs::read_to_string` wrapped in a `crate::Error`.
-    pub fn run_from_file(&mut self, path: &str) -> Result<(), Error> {
-        let contents = std::fs::read_to_string(path).map_err(|e| format!("IO Error: {}", e))?;
+    /// Will return any errors it encounters from `CommandReader` and `PaymentsEngine::run_command`, wrapped in a `crate::Error`.
+    pub fn run_from_reader<R: BufRead>(&mut self, reader: R) -> Result<(), Error> {
+        self.run_from_reader_with(reader, Mode::Strict, |_, _, _| Ok(()))
+            .map(|_| ())
+    }
 
-        let commands = parse_commands(&contents)?;
+    /// Runs payment commands streamed from a CSV reader, calling `on_outcome` with the location, command and outcome of every command that is run.
+    ///
+    /// # Errors
+    ///
+    /// Will return any errors it encounters from `CommandReader` or `PaymentsEngine::run_commands_with`.
+    pub fn run_from_reader_with<R, F>(
+        &mut self,
+        reader: R,
+        mode: Mode,
+        on_outcome: F,
+    ) -> Result<Vec<Error>, Error>
+    where
+        R: BufRead,
+        F: FnMut(&Location, &PaymentCommand, CommandOutcome) -> Result<(), Error>,
+    {
+        let commands = CommandReader::with_limits(reader, self.config.amount_limits)?;
+
+        self.run_commands_with(commands, mode, on_outcome)
+    }
 
-        self.run_commands(&commands)?;
+    /// Runs payment commands along with the location they were read from, such as those from a `CommandReader`, calling `on_outcome` with the location, command and outcome of every command that is run.
+    ///
+    /// In `Mode::Lenient`, rows that fail to parse and commands that fail to run are skipped, and the errors are returned once every command has been read.
+    ///
+    /// If a journal is open, every command is appended to it before it changes the engine, and commands it already holds are skipped, see `PaymentsEngine::open_journal`.
+    ///
+    /// # Errors
+    ///
+    /// Will return any errors it encounters from `commands`, `PaymentsEngine::run_command` or `on_outcome`, wrapped in a `crate::Error`.
+    /// In `Mode::Lenient`, only IO errors, including those from writing the journal or the stores, and errors from `on_outcome` are returned this way.
+    pub fn run_commands_with<I, F>(
+        &mut self,
+        commands: I,
+        mode: Mode,
+        mut on_outcome: F,
+    ) -> Result<Vec<Error>, Error>
+    where
+        I: IntoIterator<Item = Result<(Location, PaymentCommand), Error>>,
+        F: FnMut(&Location, &PaymentCommand, CommandOutcome) -> Result<(), Error>,
+    {
+        let mut errors = Vec::new();
+
+        for command in commands {
+            let (location, command) = match command {
+                Ok(command) => command,
+                Err(e @ Error::Parse(_)) if mode == Mode::Lenient => {
+                    errors.push(e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let Some(result) = self.run_journaled(&location, command)? else {
+                continue;
+            };
+
+            let outcome = match result {
+                Ok(outcome) => outcome,
+                // the store failing is never a problem with the row, so it is fatal even in lenient mode
+                Err(e) if mode == Mode::Lenient && !matches!(e, EngineError::Storage(_)) => {
+                    errors.push(RuntimeError(location, e).into());
+                    continue;
+                }
+                Err(e) => return Err(RuntimeError(location, e).into()),
+            };
 
-        Ok(())
+            on_outcome(&location, &command, outcome)?;
+        }
+
+        Ok(errors)
     }
 
-    // write the current state of the engine to a csv string
-    pub fn to_csv_string(&self) -> String {
-        let mut buf = String::new();
+    // runs a command read from `location`, appending it to the journal first if one is open, or returns `None` if the journal already holds it
+    pub(crate) fn run_journaled(
+        &mut self,
+        location: &Location,
+        command: PaymentCommand,
+    ) -> Result<Option<Result<CommandOutcome, EngineError>>, Error> {
+        // already run before the journal was reopened
+        if let Some(journal) = &self.journal {
+            if journal.contains(location, &command)? {
+                return Ok(None);
+            }
+        }
 
-        buf.push_str("client,available,held,total,locked");
+        let change = self.evaluate(command);
 
-        for (id, data) in &self.clients {
-            buf.push_str(&format!("\n{}", data.to_csv_string(*id)));
+        // a command whose change could not be worked out is not journaled, as it never ran
+        if let (Some(journal), false) = (
+            &mut self.journal,
+            matches!(change.result(), Err(EngineError::Storage(_))),
+        ) {
+            journal.append(location, &command, change.result())?;
+            self.journaled += 1;
         }
 
-        buf
+        Ok(Some(self.commit(change)))
+    }
+
+    /// Runs payment commands from a file, naming it in the location of any error.
+    ///
+    /// # Errors
+    ///
+    /// Will return any errors it encounters from `CommandReader` and `PaymentsEngine::run_command`, or an IO Error from opening the file, wrapped in a `crate::Error`.
+    pub fn run_from_file(&mut self, path: &str) -> Result<(), Error> {
+        let file = BufReader::new(File::open(path)?);
+        let commands = CommandReader::with_file(file, path, self.config.amount_limits)?;
+
+        self.run_commands_with(commands, Mode::Strict, |_, _, _| Ok(()))
+            .map(|_| ())
+    }
+
+    // write the current state of the engine to a csv string, in the default order
+    pub fn to_csv_string(&self) -> Result<String, Error> {
+        self.to_csv_string_ordered(AccountOrder::default())
+    }
+
+    // this is used for the tests, to ensure reproducible results whatever the default order
+    pub fn to_csv_string_sorted(&self) -> Result<String, Error> {
+        self.to_csv_string_ordered(AccountOrder::ClientId)
     }
 
-    // this is used for the tests, to ensure reproducible results
-    pub fn to_csv_string_sorted(&self) -> String {
+    pub fn to_csv_string_ordered(&self, order: AccountOrder) -> Result<String, Error> {
         let mut buf = String::new();
 
         buf.push_str("client,available,held,total,locked");
 
-        let mut clients = self.clients.iter().collect::<Vec<_>>();
-        clients.sort_by(|(x, _), (y, _)| x.cmp(y));
-
-        for (id, data) in clients {
-            buf.push_str(&format!("\n{}", data.to_csv_string(*id)));
+        for (id, data) in self.accounts_in_order(order)? {
+            buf.push_str(&format!("\n{}", data.to_csv_string(id)));
         }
 
-        buf
+        Ok(buf)
+    }
+
+    /// Writes every account with `writer`, in the given order.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from `writer` or from reading the account store.
+    pub fn write_accounts(
+        &self,
+        writer: &mut dyn AccountWriter,
+        order: AccountOrder,
+    ) -> Result<(), Error> {
+        writer.write_accounts(&self.accounts_in_order(order)?)
     }
 }
 
+/// Formats the accounts as CSV, in the default order.
+///
+/// Only an engine keeping its state in memory implements `Display`, as a failed read of any other store could only be reported by panicking.
+/// For those, use `PaymentsEngine::write_accounts` or `PaymentsEngine::to_csv_string`, which return the error instead.
 impl Display for PaymentsEngine {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.to_csv_string())
+        f.write_str(
+            &self
+                .to_csv_string()
+                .expect("the accounts of an engine in memory can always be read"),
+        )
     }
 }
-
-pub fn run() -> Result<(), Error> {
-    // the very first argument is always the name of the program, so it can be skipped
-    let mut args = std::env::args().skip(1);
-
-    let filename = args
-        .next()
-        .ok_or_else(|| Error::Other("Please specify a file to run.".to_string()))?;
-
-    let mut engine = PaymentsEngine::new();
-
-    // run commands from the specified file.
-    engine.run_from_file(&filename)?;
-
-    // get output from the engine
-    let output = engine.to_csv_string();
-
-    // print to standard output
-    print!("{output}");
-
-    Ok(())
-}
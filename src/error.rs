@@ -1,13 +1,37 @@
 use std::fmt::Display;
 
-use crate::common::*;
+use crate::{common::*, transaction::TransactionStatus};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AmountParseError {
+    Empty,
+    InvalidDigit,
+    TooPrecise,
+    Overflow,
+}
 
-#[derive(Debug, Clone)]
+impl Display for AmountParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AmountParseError::Empty => f.write_str("cannot parse amount from empty string"),
+            AmountParseError::InvalidDigit => f.write_str("invalid digit found in string"),
+            AmountParseError::TooPrecise => f.write_fmt(format_args!(
+                "more than {} decimal places",
+                crate::amount::AMOUNT_PRECISION
+            )),
+            AmountParseError::Overflow => f.write_str("amount too large to represent"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PaymentCommandParseError {
     MissingData(String),
     ParseError(String),
     NoSuchPaymentCommand(String),
     MissingHeader(String),
+    IdOutOfRange(String),
+    InvalidAmount(String),
 }
 
 impl Display for PaymentCommandParseError {
@@ -25,23 +49,38 @@ impl Display for PaymentCommandParseError {
             PaymentCommandParseError::MissingHeader(s) => {
                 f.write_fmt(format_args!("Missing Header: {}", s))
             }
+            PaymentCommandParseError::IdOutOfRange(s) => {
+                f.write_fmt(format_args!("Id Out Of Range: {}", s))
+            }
+            PaymentCommandParseError::InvalidAmount(s) => {
+                f.write_fmt(format_args!("Invalid Amount: {}", s))
+            }
         }
     }
 }
 
-/// This struct represents a `PaymentCommandParseError` occuring on a specific line.
+/// This struct represents a `PaymentCommandParseError` occuring at a specific location.
 #[derive(Debug, Clone)]
-pub struct ParseError(pub(crate) usize, pub(crate) PaymentCommandParseError);
+pub struct ParseError(pub Location, pub PaymentCommandParseError);
 
 impl Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!("[line {}] {}", self.0, self.1))
+        f.write_fmt(format_args!("[{}] {}", self.0, self.1))
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum EngineError {
     ClientIdMismatch(ClientId, ClientId),
+    AmountOverflow(ClientId),
+    DuplicateTransaction(TransactionId),
+    AccountLocked(ClientId),
+    InvalidTransition(TransactionStatus, TransactionStatus),
+    DisputedWithdrawal(TransactionId),
+    /// The amount of a deposit or withdrawal is not positive, or is outside `EngineConfig::amount_limits`.
+    InvalidAmount(PaymentCommandParseError),
+    /// The account or transaction store could not be read or written.
+    Storage(String),
 }
 
 impl Display for EngineError {
@@ -50,17 +89,61 @@ impl Display for EngineError {
             EngineError::ClientIdMismatch(id1, id2) => {
                 f.write_fmt(format_args!("Client Id Mismatch: {} != {}", id1, id2))
             }
+            EngineError::AmountOverflow(id) => {
+                f.write_fmt(format_args!("Amount Overflow: client {}", id))
+            }
+            EngineError::DuplicateTransaction(tx) => {
+                f.write_fmt(format_args!("Duplicate Transaction: {}", tx))
+            }
+            EngineError::AccountLocked(id) => {
+                f.write_fmt(format_args!("Account Locked: client {}", id))
+            }
+            EngineError::InvalidTransition(from, to) => {
+                f.write_fmt(format_args!("Invalid Transition: {} -> {}", from, to))
+            }
+            EngineError::DisputedWithdrawal(tx) => f.write_fmt(format_args!(
+                "Disputed Withdrawal: transaction {} is a withdrawal, which cannot be disputed",
+                tx
+            )),
+            EngineError::InvalidAmount(e) => f.write_fmt(format_args!("{}", e)),
+            EngineError::Storage(s) => f.write_fmt(format_args!("Storage Error: {}", s)),
         }
     }
 }
 
-/// This struct represents an `EngineError` occuring on a specific line.
+impl From<std::io::Error> for EngineError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Storage(e.to_string())
+    }
+}
+
+/// This struct represents an `EngineError` occuring at a specific location.
 #[derive(Debug, Clone)]
-pub struct RuntimeError(pub(crate) usize, pub(crate) EngineError);
+pub struct RuntimeError(pub Location, pub EngineError);
 
 impl Display for RuntimeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!("[line {}] {}", self.0, self.1))
+        f.write_fmt(format_args!("[{}] {}", self.0, self.1))
+    }
+}
+
+/// This struct represents a snapshot that could not be read, at a specific location.
+#[derive(Debug, Clone)]
+pub struct SnapshotError(pub Location, pub String);
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("[{}] Invalid Snapshot: {}", self.0, self.1))
+    }
+}
+
+/// This struct represents a journal that could not be replayed, at a specific location.
+#[derive(Debug, Clone)]
+pub struct JournalError(pub Location, pub String);
+
+impl Display for JournalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("[{}] Invalid Journal: {}", self.0, self.1))
     }
 }
 
@@ -69,6 +152,8 @@ impl Display for RuntimeError {
 pub enum Error {
     Runtime(RuntimeError),
     Parse(ParseError),
+    Snapshot(SnapshotError),
+    Journal(JournalError),
     Other(String),
 }
 
@@ -77,11 +162,25 @@ impl Display for Error {
         match self {
             Error::Runtime(x) => f.write_fmt(format_args!("{}", x)),
             Error::Parse(x) => f.write_fmt(format_args!("{}", x)),
+            Error::Snapshot(x) => f.write_fmt(format_args!("{}", x)),
+            Error::Journal(x) => f.write_fmt(format_args!("{}", x)),
             Error::Other(x) => f.write_fmt(format_args!("{}", x)),
         }
     }
 }
 
+impl From<SnapshotError> for Error {
+    fn from(e: SnapshotError) -> Self {
+        Self::Snapshot(e)
+    }
+}
+
+impl From<JournalError> for Error {
+    fn from(e: JournalError) -> Self {
+        Self::Journal(e)
+    }
+}
+
 impl From<RuntimeError> for Error {
     fn from(e: RuntimeError) -> Self {
         Self::Runtime(e)
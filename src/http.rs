@@ -0,0 +1,407 @@
+use std::{
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    iter::Peekable,
+    net::TcpStream,
+    str::Chars,
+    sync::Mutex,
+};
+
+use crate::{
+    common::*,
+    engine::{CommandOutcome, PaymentsEngine, TransactionData},
+    error::*,
+    output::account_json,
+    parse::ColumnMap,
+    server::{lock, ServerConfig},
+};
+
+// the most the request line and headers of a request may take up
+const MAX_HEAD: u64 = 16 * 1024;
+
+// the most the body of a request may take up, far more than any single command needs
+const MAX_BODY: usize = 64 * 1024;
+
+// a response, always with a JSON body
+struct Response {
+    status: u16,
+    body: String,
+    // the methods a path does allow, when it was requested with another
+    allow: Option<&'static str>,
+}
+
+impl Response {
+    fn ok(body: String) -> Self {
+        Self {
+            status: 200,
+            body,
+            allow: None,
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            body: format!(r#"{{"error":{}}}"#, json_string(message)),
+            allow: None,
+        }
+    }
+
+    fn not_allowed(allow: &'static str) -> Self {
+        Self {
+            allow: Some(allow),
+            ..Self::error(405, &format!("Only {allow} is allowed here"))
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Content Too Large",
+            422 => "Unprocessable Content",
+            500 => "Internal Server Error",
+            _ => "",
+        }
+    }
+}
+
+// the parts of a request the routes need
+struct Request {
+    method: String,
+    path: String,
+    body: String,
+}
+
+// serves the single request of an HTTP connection, answering a request that stalls with a 400
+pub(crate) fn handle(
+    engine: &Mutex<PaymentsEngine>,
+    config: ServerConfig,
+    stream: TcpStream,
+) -> Result<(), Error> {
+    // so a peer that stalls part way through its request, or never reads the response, cannot hold on to the thread
+    stream.set_read_timeout(Some(config.timeout))?;
+    stream.set_write_timeout(Some(config.timeout))?;
+
+    let response = match read_request(&mut BufReader::new(&stream)) {
+        Ok(request) => route(engine, &request),
+        Err(response) => response,
+    };
+
+    let mut writer = BufWriter::new(&stream);
+
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n",
+        response.status,
+        response.reason(),
+        response.body.len()
+    )?;
+    if let Some(allow) = response.allow {
+        write!(writer, "Allow: {allow}\r\n")?;
+    }
+    write!(writer, "Connection: close\r\n\r\n{}", response.body)?;
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+// reads the request line, headers and body, or returns the response to a malformed request
+fn read_request<R: BufRead>(reader: &mut R) -> Result<Request, Response> {
+    let bad_request = |message: &str| Response::error(400, message);
+
+    let mut head = reader.take(MAX_HEAD);
+    let mut line = String::new();
+
+    // reads the next line of the head, failing if it ends early or is too long
+    let mut next_line = |line: &mut String| {
+        line.clear();
+
+        match head.read_line(line) {
+            Ok(_) if line.ends_with('\n') => Ok(()),
+            Ok(_) => Err(bad_request(
+                "The request line and headers are incomplete or too long",
+            )),
+            Err(e) => Err(bad_request(&format!("Could not read the request: {e}"))),
+        }
+    };
+
+    next_line(&mut line)?;
+
+    let (method, path) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+        [method, target, version] if version.starts_with("HTTP/1.") => {
+            // the query string is ignored, as no route takes one
+            let path = target.split('?').next().unwrap_or_default();
+
+            (method.to_string(), path.to_string())
+        }
+        _ => return Err(bad_request("Malformed request line")),
+    };
+
+    let mut length = 0;
+
+    loop {
+        next_line(&mut line)?;
+
+        let header = line.trim_end_matches(['\r', '\n']);
+        if header.is_empty() {
+            break;
+        }
+
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(bad_request(&format!("Malformed header {header}")));
+        };
+
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            length = value
+                .trim()
+                .parse::<usize>()
+                .map_err(|e| bad_request(&format!("Invalid Content-Length {value}: {e}")))?;
+        }
+    }
+
+    if length > MAX_BODY {
+        return Err(Response::error(
+            413,
+            &format!("The body may be at most {MAX_BODY} bytes"),
+        ));
+    }
+
+    let mut body = vec![0; length];
+    reader
+        .read_exact(&mut body)
+        .map_err(|e| bad_request(&format!("Could not read the body: {e}")))?;
+
+    let body = String::from_utf8(body).map_err(|_| bad_request("The body is not UTF-8"))?;
+
+    Ok(Request { method, path, body })
+}
+
+fn route(engine: &Mutex<PaymentsEngine>, request: &Request) -> Response {
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+
+    match (request.method.as_str(), &segments[..]) {
+        ("POST", ["transactions"]) => submit(engine, &request.body),
+        ("GET", ["clients", id]) => get_client(engine, id),
+        ("GET", ["transactions", tx]) => get_transaction(engine, tx),
+        (_, ["transactions"]) => Response::not_allowed("POST"),
+        (_, ["clients" | "transactions", _]) => Response::not_allowed("GET"),
+        _ => Response::error(404, &format!("There is nothing at {}", request.path)),
+    }
+}
+
+// runs a command given as JSON, responding with whether it was applied or why it was ignored
+fn submit(engine: &Mutex<PaymentsEngine>, body: &str) -> Response {
+    let command = match parse_command(body) {
+        Ok(command) => command,
+        Err(message) => return Response::error(400, &message),
+    };
+
+    let mut engine = lock(engine);
+
+    if let Err(e) = engine.config().amount_limits.check(&command) {
+        return Response::error(400, &e.to_string());
+    }
+
+    match engine.run_command(command) {
+        Ok(CommandOutcome::Applied) => Response::ok(r#"{"result":"applied"}"#.to_string()),
+        Ok(CommandOutcome::Ignored(reason)) => Response::ok(format!(
+            r#"{{"result":"ignored","reason":{}}}"#,
+            json_string(&reason.to_string())
+        )),
+        Err(e) => Response::error(422, &e.to_string()),
+    }
+}
+
+fn get_client(engine: &Mutex<PaymentsEngine>, id: &str) -> Response {
+    let id = match id.parse::<ClientId>() {
+        Ok(id) => id,
+        Err(e) => return Response::error(400, &format!("Invalid client id {id}: {e}")),
+    };
+
+    match lock(engine).get_account(id) {
+        Ok(Some(data)) => Response::ok(account_json(id, &data)),
+        Ok(None) => Response::error(404, &format!("Client {id} has no account")),
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+fn get_transaction(engine: &Mutex<PaymentsEngine>, tx: &str) -> Response {
+    let tx = match tx.parse::<TransactionId>() {
+        Ok(tx) => tx,
+        Err(e) => return Response::error(400, &format!("Invalid transaction id {tx}: {e}")),
+    };
+
+    match lock(engine).get_transaction(tx) {
+        Ok(Some(data)) => Response::ok(transaction_json(tx, &data)),
+        Ok(None) => Response::error(404, &format!("There is no transaction {tx}")),
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+fn transaction_json(tx: TransactionId, data: &TransactionData) -> String {
+    format!(
+        r#"{{"tx":{tx},"client":{},"type":"{}","amount":{},"status":"{}"}}"#,
+        data.client(),
+        data.kind(),
+        data.amount(),
+        data.status()
+    )
+}
+
+// parses a command from a JSON object with the same fields as the CSV columns, such as `{"type":"deposit","client":1,"tx":1,"amount":"1.5"}`
+fn parse_command(body: &str) -> Result<PaymentCommand, String> {
+    let fields = parse_object(body)?;
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .and_then(|(_, value)| value.clone())
+    };
+
+    let mut record = Vec::with_capacity(4);
+    for name in ["type", "client", "tx"] {
+        record.push(field(name).ok_or_else(|| format!("The command has no {name}"))?);
+    }
+    // like an empty cell, a missing or null amount is only an error for deposits and withdrawals
+    record.push(field("amount").unwrap_or_default());
+
+    ColumnMap::default()
+        .parse(&record)
+        .map_err(|e| e.to_string())
+}
+
+// parses a JSON object of strings, numbers, booleans and nulls, keeping every value as its text, or `None` for null
+fn parse_object(json: &str) -> Result<Vec<(String, Option<String>)>, String> {
+    let mut chars = json.chars().peekable();
+    let mut fields = Vec::new();
+
+    let skip_whitespace = |chars: &mut Peekable<Chars>| {
+        while chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+    };
+
+    skip_whitespace(&mut chars);
+    if chars.next() != Some('{') {
+        return Err("The body must be a JSON object".to_string());
+    }
+
+    skip_whitespace(&mut chars);
+    if chars.next_if_eq(&'}').is_none() {
+        loop {
+            skip_whitespace(&mut chars);
+            let key = parse_string(&mut chars)?;
+
+            skip_whitespace(&mut chars);
+            if chars.next() != Some(':') {
+                return Err(format!("Expected : after {key}"));
+            }
+
+            skip_whitespace(&mut chars);
+            let value = match chars.peek() {
+                Some('"') => Some(parse_string(&mut chars)?),
+                _ => {
+                    let mut word = String::new();
+                    while let Some(c) =
+                        chars.next_if(|c| c.is_ascii_alphanumeric() || "+-.".contains(*c))
+                    {
+                        word.push(c);
+                    }
+
+                    match word.as_str() {
+                        "null" => None,
+                        "true" | "false" => Some(word),
+                        _ if word.starts_with(|c: char| c == '-' || c.is_ascii_digit()) => {
+                            Some(word)
+                        }
+                        _ => {
+                            return Err(format!(
+                                "Expected a string, number, boolean or null for {key}"
+                            ))
+                        }
+                    }
+                }
+            };
+
+            fields.push((key, value));
+
+            skip_whitespace(&mut chars);
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                _ => return Err("Expected , or } between the fields".to_string()),
+            }
+        }
+    }
+
+    skip_whitespace(&mut chars);
+    if chars.next().is_some() {
+        return Err("Unexpected data after the object".to_string());
+    }
+
+    Ok(fields)
+}
+
+// parses a JSON string, starting at its opening quote
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    if chars.next() != Some('"') {
+        return Err("Expected a string".to_string());
+    }
+
+    let mut string = String::new();
+
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(string),
+            Some('\\') => {
+                let c = match chars.next() {
+                    Some(c @ ('"' | '\\' | '/')) => c,
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('u') => {
+                        let hex: String = chars.by_ref().take(4).collect();
+
+                        // characters outside the basic multilingual plane are never needed in a command
+                        u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| format!("Unsupported escape \\u{hex}"))?
+                    }
+                    _ => return Err("Invalid escape in a string".to_string()),
+                };
+
+                string.push(c);
+            }
+            Some(c) if c.is_control() => {
+                return Err("Unescaped control character in a string".to_string())
+            }
+            Some(c) => string.push(c),
+            None => return Err("Unterminated string".to_string()),
+        }
+    }
+}
+
+// a string as a JSON string, quotes included
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+
+    json
+}
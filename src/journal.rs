@@ -0,0 +1,327 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    sync::Arc,
+};
+
+use crate::{
+    common::*,
+    engine::{CommandOutcome, PaymentsEngine},
+    error::*,
+    parse::{split_record, ColumnMap},
+    report::escape_field,
+    store::{AccountStore, TransactionStore},
+};
+
+// the first line of every journal, to be bumped whenever the format changes
+const VERSION: &str = "# payments-engine journal v2";
+
+// the line after the version, counting the commands journaled before the first entry, so the journal can be matched to a snapshot
+const JOURNALED: &str = "journaled";
+
+const HEADER: &str = "file,line,type,client,tx,amount,result";
+
+// the columns of the header that are not part of the command
+const FILE: usize = 0;
+const LINE: usize = 1;
+const RESULT: usize = 6;
+
+// the text a result is recorded as, which is also checked against when replaying
+fn result_string(result: &Result<CommandOutcome, EngineError>) -> String {
+    match result {
+        Ok(outcome) => outcome.to_string(),
+        Err(e) => format!("error: {e}"),
+    }
+}
+
+// the fields of an entry that hold the command, which are also how a command is named in errors
+fn command_string(command: &PaymentCommand) -> String {
+    format!(
+        "{},{},{},{}",
+        command.name(),
+        command.client(),
+        command.tx(),
+        command.amount().map(|x| x.to_string()).unwrap_or_default(),
+    )
+}
+
+fn write_header(file: &mut File, journaled: u64) -> Result<(), Error> {
+    file.write_all(format!("{VERSION}\n{JOURNALED},{journaled}\n{HEADER}\n").as_bytes())?;
+    file.sync_data()?;
+
+    Ok(())
+}
+
+/// A write-ahead log of every command run by a `PaymentsEngine`, see `PaymentsEngine::open_journal`.
+#[derive(Debug)]
+pub(crate) struct Journal {
+    file: File,
+    // the last line journaled from each file, so rows already run are skipped when the same files are run again
+    progress: HashMap<Option<Arc<str>>, usize>,
+    // the command journaled from each row, so a row that is skipped can be checked to still hold it
+    commands: HashMap<(Option<Arc<str>>, usize), PaymentCommand>,
+}
+
+impl Journal {
+    /// Whether the command at `location` was already run before the journal was opened.
+    ///
+    /// # Errors
+    ///
+    /// Will return a `JournalError` if the journal has already read past the row, but the row does not hold the command journaled from it, such as when a file of the same name has different contents.
+    pub(crate) fn contains(
+        &self,
+        location: &Location,
+        command: &PaymentCommand,
+    ) -> Result<bool, Error> {
+        if self
+            .progress
+            .get(&location.file)
+            .is_none_or(|line| location.line > *line)
+        {
+            return Ok(false);
+        }
+
+        let mismatch = |message: String| Err(JournalError(location.clone(), message).into());
+
+        match self.commands.get(&(location.file.clone(), location.line)) {
+            Some(journaled) if journaled == command => Ok(true),
+            Some(journaled) => mismatch(format!(
+                "the row was journaled as {}, but now holds {}",
+                command_string(journaled),
+                command_string(command)
+            )),
+            None => mismatch(format!(
+                "the row holds {}, but was never journaled, though later rows of its file were",
+                command_string(command)
+            )),
+        }
+    }
+
+    /// Appends a command and its result, returning only once the entry has been synced to disk.
+    pub(crate) fn append(
+        &mut self,
+        location: &Location,
+        command: &PaymentCommand,
+        result: &Result<CommandOutcome, EngineError>,
+    ) -> Result<(), Error> {
+        // the entry is written in a single call, so a crash can only ever tear the last line
+        let entry = format!(
+            "{},{},{},{}\n",
+            escape_field(location.file.as_deref().unwrap_or_default()),
+            location.line,
+            command_string(command),
+            escape_field(&result_string(result)),
+        );
+
+        self.file.write_all(entry.as_bytes())?;
+        self.file.sync_data()?;
+
+        Ok(())
+    }
+
+    // empties the journal back to its header, which a crash part way through leaves as an empty file, the same as a new journal
+    fn clear(&mut self, journaled: u64) -> Result<(), Error> {
+        self.file.set_len(0)?;
+        write_header(&mut self.file, journaled)?;
+
+        self.progress.clear();
+        self.commands.clear();
+
+        Ok(())
+    }
+}
+
+impl<A: AccountStore, T: TransactionStore> PaymentsEngine<A, T> {
+    /// Opens the journal at `path`, creating it if it does not exist, and replays every command already in it.
+    ///
+    /// From then on, every command run through `PaymentsEngine::run_commands_with`, and so `run_from_reader` and `run_from_file`, is appended to the journal and synced to disk before it changes the engine.
+    /// Commands run with `PaymentsEngine::run_command` or `PaymentsEngine::run_commands` are not journaled.
+    ///
+    /// Commands are identified by the file and line they were read from, so after a crash, running the same files again skips the rows that were already journaled, once each has been checked to still hold the command journaled from it.
+    /// The journal must be replayed onto the same state it was written from, such as a new engine or the same restored snapshot, see `PaymentsEngine::checkpoint_journal`.
+    /// Both the journal and snapshots count the commands ever journaled, so the entries a restored snapshot already holds are skipped rather than run again.
+    /// A last entry that was only partly written when the process stopped is discarded, as the command it describes was never applied.
+    ///
+    /// # Errors
+    ///
+    /// Will return a `JournalError` if the journal is not in the expected format, has an unsupported version, does not continue from the state of the engine, or a replayed command does not give the result it was journaled with, or any IO error from opening, reading, or truncating the file.
+    /// Running a row the journal has already read past, but that no longer holds the command journaled from it, fails with a `JournalError` too.
+    pub fn open_journal(&mut self, path: &str) -> Result<(), Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let mut journal = Journal {
+            file,
+            progress: HashMap::new(),
+            commands: HashMap::new(),
+        };
+
+        if journal.file.metadata()?.len() == 0 {
+            write_header(&mut journal.file, self.journaled)?;
+        } else {
+            let end = self.replay(&mut journal, path.into())?;
+
+            // drop a torn last entry, so the next one starts on a line of its own
+            if end < journal.file.metadata()?.len() {
+                journal.file.set_len(end)?;
+                journal.file.sync_data()?;
+            }
+        }
+
+        self.journal = Some(journal);
+
+        Ok(())
+    }
+
+    /// Empties the open journal, once every command in it is held by a snapshot, such as one just saved by `PaymentsEngine::save_snapshot`.
+    ///
+    /// From then on, the journal only holds the commands run since that snapshot, and must be replayed onto it.
+    /// If the process stops after the snapshot is saved but before the journal is emptied, the journal is still opened on the restored snapshot, skipping every entry the snapshot holds.
+    /// Does nothing if no journal is open.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from truncating or writing the journal.
+    pub fn checkpoint_journal(&mut self) -> Result<(), Error> {
+        match &mut self.journal {
+            Some(journal) => journal.clear(self.journaled),
+            None => Ok(()),
+        }
+    }
+
+    // runs every complete entry of the journal the engine does not already hold, returning the offset just past the last one
+    fn replay(&mut self, journal: &mut Journal, path: Arc<str>) -> Result<u64, Error> {
+        let mut reader = BufReader::new(&journal.file);
+        let mut buf = String::new();
+        let mut location = Location {
+            file: Some(path),
+            ..Default::default()
+        };
+
+        // the location is of the start of the line just read
+        let mut next_line = |location: &mut Location, buf: &mut String| -> Result<bool, Error> {
+            location.offset += buf.len() as u64;
+            buf.clear();
+            let read = reader.read_line(buf)?;
+
+            // a line without its line ending was torn by a crash while it was being written
+            if read == 0 || !buf.ends_with('\n') {
+                return Ok(false);
+            }
+
+            location.line += 1;
+
+            Ok(true)
+        };
+        let error = |location: &Location, message: String| -> Error {
+            JournalError(location.clone(), message).into()
+        };
+
+        // the entries the engine already holds, such as those in a snapshot saved just before the journal was to be emptied
+        let mut held = 0;
+
+        for expected in [VERSION, JOURNALED, HEADER] {
+            if !next_line(&mut location, &mut buf)? {
+                return Err(error(&location, format!("expected {expected}")));
+            }
+
+            let line = buf.trim_end_matches(['\n', '\r']);
+
+            if expected != JOURNALED {
+                if line != expected {
+                    return Err(error(
+                        &location,
+                        format!("expected {expected}, found {line}"),
+                    ));
+                }
+
+                continue;
+            }
+
+            let Some(Ok(base)) = line.strip_prefix(&format!("{JOURNALED},")).map(str::parse) else {
+                return Err(error(
+                    &location,
+                    format!("expected {JOURNALED},<count>, found {line}"),
+                ));
+            };
+
+            held = self.journaled.checked_sub(base).ok_or_else(|| {
+                error(
+                    &location,
+                    format!(
+                        "the journal continues from {base} commands journaled, but the engine only holds {}, so it must be opened on the snapshot it continues from",
+                        self.journaled
+                    ),
+                )
+            })?;
+        }
+
+        let columns = ColumnMap::from_header(&split_record(HEADER).expect("valid header"))
+            .expect("valid header");
+        let mut end = location.offset + buf.len() as u64;
+
+        while next_line(&mut location, &mut buf)? {
+            let fields = split_record(buf.trim_end_matches(['\n', '\r']))
+                .map_err(|e| error(&location, e.to_string()))?;
+
+            if fields.len() != 7 {
+                return Err(error(
+                    &location,
+                    format!("expected 7 fields, found {}", fields.len()),
+                ));
+            }
+
+            let command = columns
+                .parse(&fields)
+                .map_err(|e| error(&location, e.to_string()))?;
+            let line = fields[LINE].parse::<usize>().map_err(|e| {
+                error(
+                    &location,
+                    format!("could not parse line {}: {e}", fields[LINE]),
+                )
+            })?;
+            let file = Some(fields[FILE].as_ref())
+                .filter(|x| !x.is_empty())
+                .map(Arc::from);
+
+            if held > 0 {
+                held -= 1;
+            } else {
+                let result = result_string(&self.run_command(command));
+                if result != fields[RESULT] {
+                    return Err(error(
+                        &location,
+                        format!(
+                            "the command was journaled as {}, but replaying it gave {result}",
+                            fields[RESULT]
+                        ),
+                    ));
+                }
+
+                self.journaled += 1;
+            }
+
+            let last = journal.progress.entry(file.clone()).or_default();
+            *last = (*last).max(line);
+            journal.commands.insert((file, line), command);
+            end = location.offset + buf.len() as u64;
+        }
+
+        if held > 0 {
+            return Err(error(
+                &location,
+                format!(
+                    "the engine holds {} commands journaled, but the journal ends after {}",
+                    self.journaled,
+                    self.journaled - held
+                ),
+            ));
+        }
+
+        Ok(end)
+    }
+}
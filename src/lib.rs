@@ -0,0 +1,46 @@
+//! A payments engine that reads transactions from CSV, applies them to client accounts, and reports the resulting balances.
+
+// all clippy warnings
+#![warn(clippy::all)]
+
+mod amount;
+mod common;
+mod engine;
+mod error;
+mod http;
+mod journal;
+mod output;
+mod parallel;
+mod parse;
+mod report;
+mod server;
+mod snapshot;
+mod store;
+#[cfg(feature = "async")]
+mod stream;
+mod transaction;
+
+// re-export
+pub use amount::{Amount, AMOUNT_PRECISION};
+pub use common::*;
+pub use engine::{
+    ClientData, CommandOutcome, EngineConfig, IgnoreReason, Mode, PaymentsEngine, TransactionData,
+    WithdrawalDisputePolicy,
+};
+pub use error::*;
+pub use output::{
+    AccountOrder, AccountWriter, CsvWriter, FixedWidthWriter, JsonLinesWriter, OutputFormat,
+    TableWriter,
+};
+pub use parse::{split_record, AmountLimits, ColumnMap, CommandReader};
+pub use report::{HistoryWriter, RejectionWriter};
+pub use server::{Server, ServerConfig, ACCOUNTS_QUERY};
+pub use store::{AccountStore, DiskAccountStore, DiskTransactionStore, TransactionStore};
+#[cfg(feature = "async")]
+pub use stream::{AsyncCommandReader, Outcomes};
+pub use transaction::{
+    RedisputePolicy, TransactionEvent, TransactionKind, TransactionStatus, TransitionPolicy,
+};
+
+#[cfg(test)]
+mod tests;
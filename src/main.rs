@@ -1,28 +1,308 @@
 // all clippy warnings
 #![warn(clippy::all)]
 
-mod common;
-mod engine;
-mod error;
-mod parse;
+mod cli;
 
-// re-export
-pub use common::*;
-pub use engine::PaymentsEngine;
-pub use error::Error;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    net::TcpListener,
+};
 
-// wrapper function to print the error message using Display instead of Debug
+use cli::{Command, Options};
+use payments_engine::{
+    AmountLimits, ClientId, CommandOutcome, CommandReader, EngineConfig, Error, HistoryWriter,
+    Location, Mode, PaymentCommand, PaymentsEngine, RejectionWriter, RuntimeError, Server,
+    ServerConfig,
+};
+
+// wrapper function to print the error message using Display instead of Debug, and exit with a code describing it
 fn main() {
-    match engine::run() {
-        Ok(()) => {}
+    let command = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{e}\n\nRun with --help for usage.");
+            std::process::exit(cli::EXIT_USAGE);
+        }
+    };
+
+    let code = match run(command) {
+        Ok(code) => code,
         Err(e) => {
             // print error message
             eprintln!("{}", e);
-            // exit with code 1 to signify an error
-            std::process::exit(1);
+
+            match e {
+                Error::Parse(_) => cli::EXIT_PARSE,
+                Error::Runtime(_) => cli::EXIT_RUNTIME,
+                Error::Snapshot(_) => cli::EXIT_SNAPSHOT,
+                Error::Journal(_) => cli::EXIT_JOURNAL,
+                Error::Other(_) => cli::EXIT_OTHER,
+            }
+        }
+    };
+
+    std::process::exit(code);
+}
+
+// the name given to standard input in error messages and the rejection log
+const STDIN_NAME: &str = "<stdin>";
+
+// runs the command, returning the code to exit with
+fn run(command: Command) -> Result<i32, Error> {
+    match command {
+        Command::Process(options) => process(&options),
+        Command::Validate(options) => validate(&options),
+        Command::Inspect(client, options) => inspect(client, &options),
+        Command::Serve(options) => serve(&options),
+        Command::Help => {
+            print!("{}", cli::HELP);
+            Ok(0)
+        }
+        Command::Version => {
+            println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+            Ok(0)
+        }
+    }
+}
+
+// opens an input file, or standard input for -, along with the name to report it by
+fn open_input(filename: &str) -> Result<(&str, Box<dyn BufRead>), Error> {
+    if filename == "-" {
+        return Ok((STDIN_NAME, Box::new(std::io::stdin().lock())));
+    }
+
+    let file = File::open(filename)
+        .map_err(|e| Error::Other(format!("Could not open {filename}: {e}")))?;
+
+    Ok((filename, Box::new(BufReader::new(file))))
+}
+
+// creates the output file, or standard output if there is none
+fn create_output(filename: Option<&str>) -> Result<Box<dyn Write>, Error> {
+    match filename {
+        Some(filename) => {
+            let file = File::create(filename)
+                .map_err(|e| Error::Other(format!("Could not create {filename}: {e}")))?;
+
+            Ok(Box::new(BufWriter::new(file)))
+        }
+        None => Ok(Box::new(std::io::stdout().lock())),
+    }
+}
+
+// a row skipped in lenient mode, along with its command if the row could be parsed
+type Skipped = (Error, Option<PaymentCommand>);
+
+// runs the commands from each file in order, returning the rows skipped in lenient mode
+fn run_files<F>(
+    engine: &mut PaymentsEngine,
+    options: &Options,
+    mut on_outcome: F,
+) -> Result<Vec<Skipped>, Error>
+where
+    F: FnMut(&Location, &PaymentCommand, CommandOutcome) -> Result<(), Error>,
+{
+    let mut skipped = Vec::new();
+
+    for filename in &options.files {
+        let (name, reader) = open_input(filename)?;
+
+        // the commands read but not yet given an outcome, so a command that fails to run can be reported along with its error
+        let pending = RefCell::new(HashMap::new());
+        let commands = CommandReader::with_file(reader, name, engine.config().amount_limits)?
+            .inspect(|command| {
+                if let Ok((location, command)) = command {
+                    pending.borrow_mut().insert(location.line, *command);
+                }
+            });
+        let mut on_outcome = |location: &Location, command: &PaymentCommand, outcome| {
+            pending.borrow_mut().remove(&location.line);
+            on_outcome(location, command, outcome)
+        };
+
+        let errors = match options.threads {
+            Some(threads) => {
+                engine.run_commands_parallel(commands, threads, options.mode, &mut on_outcome)?
+            }
+            None => engine.run_commands_with(commands, options.mode, &mut on_outcome)?,
+        };
+
+        let mut pending = pending.into_inner();
+        skipped.extend(errors.into_iter().map(|e| {
+            let command = match &e {
+                Error::Runtime(RuntimeError(location, _)) => pending.remove(&location.line),
+                _ => None,
+            };
+
+            (e, command)
+        }));
+    }
+
+    // in lenient mode, report the skipped rows without failing the run
+    for (e, _) in &skipped {
+        eprintln!("{e}");
+    }
+
+    Ok(skipped)
+}
+
+fn process(options: &Options) -> Result<i32, Error> {
+    let mut rejections = match &options.rejections {
+        Some(rejections_filename) => Some(RejectionWriter::new(create_output(Some(
+            rejections_filename,
+        ))?)?),
+        None => None,
+    };
+
+    // resume from the state of an earlier run if asked to
+    let mut engine = match &options.restore {
+        Some(restore_filename) => {
+            PaymentsEngine::load_snapshot(restore_filename, EngineConfig::default())?
+        }
+        None => PaymentsEngine::new(),
+    };
+
+    // replay the commands of an interrupted run, and journal the new ones
+    if let Some(journal_filename) = &options.journal {
+        engine.open_journal(journal_filename)?;
+    }
+
+    // log ignored commands if asked to
+    let skipped = run_files(&mut engine, options, |location, command, outcome| {
+        match (outcome, &mut rejections) {
+            (CommandOutcome::Ignored(reason), Some(rejections)) => {
+                rejections.write(location, command, reason)
+            }
+            _ => Ok(()),
+        }
+    })?;
+
+    // followed by the rows skipped in lenient mode, once they are known
+    if let Some(rejections) = &mut rejections {
+        for (e, command) in &skipped {
+            rejections.write_error(e, command.as_ref())?;
+        }
+
+        rejections.flush()?;
+    }
+
+    // the snapshot now holds every journaled command, so the journal starts over from it
+    if let Some(snapshot_filename) = &options.snapshot {
+        engine.save_snapshot(snapshot_filename)?;
+        engine.checkpoint_journal()?;
+    }
+
+    // write the accounts straight to the output
+    let mut writer = options
+        .format
+        .writer(create_output(options.output.as_deref())?);
+
+    engine.write_accounts(writer.as_mut(), options.order)?;
+
+    Ok(0)
+}
+
+fn validate(options: &Options) -> Result<i32, Error> {
+    let mut failed = false;
+
+    for filename in &options.files {
+        let (name, reader) = open_input(filename)?;
+        let (mut commands, mut errors) = (0, 0);
+
+        for command in CommandReader::with_file(reader, name, AmountLimits::default())? {
+            match command {
+                Ok(_) => commands += 1,
+                Err(e @ Error::Parse(_)) if options.mode == Mode::Lenient => {
+                    eprintln!("{e}");
+                    errors += 1;
+                }
+                Err(e) => return Err(e),
+            }
         }
+
+        println!("{name}: {commands} commands, {errors} errors");
+        failed |= errors > 0;
     }
+
+    // unlike a lenient run, a lenient validation still fails if any row is invalid
+    Ok(if failed { cli::EXIT_PARSE } else { 0 })
 }
 
-#[cfg(test)]
-mod tests;
+fn inspect(client: ClientId, options: &Options) -> Result<i32, Error> {
+    let mut history = HistoryWriter::new(create_output(options.output.as_deref())?)?;
+    let mut engine = PaymentsEngine::new();
+
+    // the history is written as the commands are run, so it is kept up to the error in strict mode
+    let result = run_files(&mut engine, options, |location, command, outcome| {
+        if command.client() == client {
+            history.write(location, command, outcome)
+        } else {
+            Ok(())
+        }
+    });
+
+    history.flush()?;
+    result?;
+
+    Ok(0)
+}
+
+// binds a listener, reporting the address it is bound to, which has the port chosen when given port 0
+fn bind(address: &str, name: &str) -> Result<TcpListener, Error> {
+    let listener = TcpListener::bind(address)
+        .map_err(|e| Error::Other(format!("Could not listen on {address}: {e}")))?;
+    eprintln!("Serving {name} on {}", listener.local_addr()?);
+
+    Ok(listener)
+}
+
+// reports a connection that failed, without stopping the server
+fn report(e: &Error) {
+    eprintln!("{e}");
+}
+
+fn serve(options: &Options) -> Result<i32, Error> {
+    let engine = match &options.restore {
+        Some(restore_filename) => {
+            PaymentsEngine::load_snapshot(restore_filename, EngineConfig::default())?
+        }
+        None => PaymentsEngine::new(),
+    };
+
+    let config = ServerConfig {
+        mode: options.mode,
+        format: options.format,
+        order: options.order,
+        ..Default::default()
+    };
+    let server = Server::new(engine, config);
+
+    let listener = options
+        .listen
+        .as_deref()
+        .map(|address| bind(address, "CSV streams"))
+        .transpose()?;
+    let http_listener = options
+        .http
+        .as_deref()
+        .map(|address| bind(address, "HTTP"))
+        .transpose()?;
+
+    // both run until the process is stopped, sharing the same accounts
+    match (listener, http_listener) {
+        (Some(listener), Some(http_listener)) => {
+            let http_server = server.clone();
+            std::thread::spawn(move || http_server.serve_http(http_listener, report));
+
+            server.serve(listener, report);
+        }
+        (Some(listener), None) => server.serve(listener, report),
+        (None, Some(http_listener)) => server.serve_http(http_listener, report),
+        (None, None) => {}
+    }
+
+    Ok(0)
+}
@@ -0,0 +1,283 @@
+use std::{fmt::Display, io::Write, str::FromStr};
+
+use crate::{common::*, engine::ClientData, error::*};
+
+// width of an amount with every digit of an `i64`, a decimal point and a sign
+const AMOUNT_WIDTH: usize = 21;
+
+// width of a client id with every digit of a `u32`
+const CLIENT_WIDTH: usize = 10;
+
+/// Writes the state of a set of accounts in some format.
+///
+/// Accounts are written straight to the underlying writer, one row at a time, rather than being collected into a `String` first.
+pub trait AccountWriter {
+    /// Writes the accounts in the order given, flushing the underlying writer once done.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from writing.
+    fn write_accounts(&mut self, accounts: &[(ClientId, ClientData)]) -> Result<(), Error>;
+}
+
+/// The formats accounts can be written in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// CSV with a `client,available,held,total,locked` header, see `CsvWriter`.
+    #[default]
+    Csv,
+    /// A JSON object per line, see `JsonLinesWriter`.
+    JsonLines,
+    /// An aligned table for reading in a terminal, see `TableWriter`.
+    Table,
+    /// Fixed-width records, see `FixedWidthWriter`.
+    FixedWidth,
+}
+
+impl OutputFormat {
+    /// Creates an `AccountWriter` for this format, writing to `writer`.
+    pub fn writer<'a, W: Write + 'a>(self, writer: W) -> Box<dyn AccountWriter + 'a> {
+        match self {
+            OutputFormat::Csv => Box::new(CsvWriter::new(writer)),
+            OutputFormat::JsonLines => Box::new(JsonLinesWriter::new(writer)),
+            OutputFormat::Table => Box::new(TableWriter::new(writer)),
+            OutputFormat::FixedWidth => Box::new(FixedWidthWriter::new(writer)),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    /// Parses the name of a format, as printed by `Display`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "jsonl" => Ok(OutputFormat::JsonLines),
+            "table" => Ok(OutputFormat::Table),
+            "fixed" => Ok(OutputFormat::FixedWidth),
+            _ => Err(format!(
+                "Unknown output format {s}, expected csv, jsonl, table or fixed."
+            )),
+        }
+    }
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputFormat::Csv => f.write_str("csv"),
+            OutputFormat::JsonLines => f.write_str("jsonl"),
+            OutputFormat::Table => f.write_str("table"),
+            OutputFormat::FixedWidth => f.write_str("fixed"),
+        }
+    }
+}
+
+/// The order accounts are written in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AccountOrder {
+    /// Ascending client id.
+    #[default]
+    ClientId,
+    /// Descending total funds, so the largest accounts come first, with ties in ascending client id.
+    Total,
+    /// The order the accounts were created in, by the first command for each client.
+    Insertion,
+}
+
+impl FromStr for AccountOrder {
+    type Err = String;
+
+    /// Parses the name of an order, as printed by `Display`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(AccountOrder::ClientId),
+            "total" => Ok(AccountOrder::Total),
+            "insertion" => Ok(AccountOrder::Insertion),
+            _ => Err(format!(
+                "Unknown order {s}, expected client, total or insertion."
+            )),
+        }
+    }
+}
+
+impl Display for AccountOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccountOrder::ClientId => f.write_str("client"),
+            AccountOrder::Total => f.write_str("total"),
+            AccountOrder::Insertion => f.write_str("insertion"),
+        }
+    }
+}
+
+/// Writes accounts as CSV, with a header and one row per account.
+pub struct CsvWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> CsvWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> AccountWriter for CsvWriter<W> {
+    fn write_accounts(&mut self, accounts: &[(ClientId, ClientData)]) -> Result<(), Error> {
+        self.writer
+            .write_all(b"client,available,held,total,locked\n")?;
+
+        for (id, data) in accounts {
+            writeln!(
+                self.writer,
+                "{id},{},{},{},{}",
+                data.available(),
+                data.held(),
+                data.total(),
+                data.locked()
+            )?;
+        }
+
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+// an account as a JSON object, with the same fields as the CSV columns
+pub(crate) fn account_json(id: ClientId, data: &ClientData) -> String {
+    format!(
+        r#"{{"client":{id},"available":{},"held":{},"total":{},"locked":{}}}"#,
+        data.available(),
+        data.held(),
+        data.total(),
+        data.locked()
+    )
+}
+
+/// Writes accounts as JSON lines, one object per account with the same fields as the CSV columns.
+///
+/// Amounts are written as JSON numbers with all four decimal places, so they are never rounded by the writer.
+pub struct JsonLinesWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonLinesWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> AccountWriter for JsonLinesWriter<W> {
+    fn write_accounts(&mut self, accounts: &[(ClientId, ClientData)]) -> Result<(), Error> {
+        for (id, data) in accounts {
+            writeln!(self.writer, "{}", account_json(*id, data))?;
+        }
+
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Writes accounts as a table for reading in a terminal, with every column as wide as its widest value.
+pub struct TableWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> TableWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> AccountWriter for TableWriter<W> {
+    fn write_accounts(&mut self, accounts: &[(ClientId, ClientData)]) -> Result<(), Error> {
+        const HEADER: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+        // the widths are found in a first pass, so that the rows can be written straight away in the second
+        let mut widths = HEADER.map(str::len);
+        for (id, data) in accounts {
+            let row = [
+                id.to_string().len(),
+                data.available().to_string().len(),
+                data.held().to_string().len(),
+                data.total().to_string().len(),
+                data.locked().to_string().len(),
+            ];
+
+            for (width, len) in widths.iter_mut().zip(row) {
+                *width = (*width).max(len);
+            }
+        }
+
+        for (i, (name, width)) in HEADER.iter().zip(widths).enumerate() {
+            let separator = if i == 0 { "" } else { " | " };
+            write!(self.writer, "{separator}{name:>width$}")?;
+        }
+        writeln!(self.writer)?;
+
+        for (i, width) in widths.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "-+-" };
+            write!(self.writer, "{separator}{:-<width$}", "")?;
+        }
+        writeln!(self.writer)?;
+
+        let [client, available, held, total, locked] = widths;
+        for (id, data) in accounts {
+            writeln!(
+                self.writer,
+                "{id:>client$} | {:>available$} | {:>held$} | {:>total$} | {:>locked$}",
+                data.available(),
+                data.held(),
+                data.total(),
+                data.locked()
+            )?;
+        }
+
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Writes accounts as fixed-width records, without a header, for systems that cannot read delimited files.
+///
+/// Every field is right-aligned and padded with spaces, with a single space between fields:
+///
+/// | Field     | Columns |
+/// | --------- | ------- |
+/// | client    | 1-10    |
+/// | available | 12-32   |
+/// | held      | 34-54   |
+/// | total     | 56-76   |
+/// | locked    | 78-82   |
+pub struct FixedWidthWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> FixedWidthWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> AccountWriter for FixedWidthWriter<W> {
+    fn write_accounts(&mut self, accounts: &[(ClientId, ClientData)]) -> Result<(), Error> {
+        for (id, data) in accounts {
+            writeln!(
+                self.writer,
+                "{id:>CLIENT_WIDTH$} {:>AMOUNT_WIDTH$} {:>AMOUNT_WIDTH$} {:>AMOUNT_WIDTH$} {:>5}",
+                data.available(),
+                data.held(),
+                data.total(),
+                data.locked()
+            )?;
+        }
+
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    collections::{hash_map::Entry as MapEntry, BTreeMap, HashMap},
+    num::NonZeroUsize,
+    sync::mpsc::{self, Receiver, Sender, SyncSender},
+    thread,
+};
+
+use crate::{
+    common::*,
+    engine::{ClientData, CommandOutcome, Mode, PaymentsEngine, TransactionData},
+    error::*,
+    parse::AmountLimits,
+};
+
+// the number of commands read before they are handed to the shards
+const BATCH: usize = 1024;
+
+// the number of batches a shard may have queued before the reader waits for it
+const QUEUE: usize = 4;
+
+// a command, along with its position in the input
+type Job = (usize, Location, PaymentCommand);
+
+// what became of the command at some position in the input
+enum Entry {
+    Ran(
+        Location,
+        PaymentCommand,
+        Result<CommandOutcome, EngineError>,
+    ),
+    // the row could not be read
+    Failed(Error),
+}
+
+// hands the entries to `on_outcome` in the order of the input, whatever order the shards finish them in
+struct Ordered<F> {
+    pending: BTreeMap<usize, Entry>,
+    next: usize,
+    mode: Mode,
+    errors: Vec<Error>,
+    on_outcome: F,
+}
+
+impl<F> Ordered<F>
+where
+    F: FnMut(&Location, &PaymentCommand, CommandOutcome) -> Result<(), Error>,
+{
+    // handles every entry that follows on from those already handled, returning the error that ends the run, if any
+    fn drain(&mut self) -> Result<(), Error> {
+        while let Some(entry) = self.pending.remove(&self.next) {
+            self.next += 1;
+
+            match entry {
+                Entry::Ran(location, command, Ok(outcome)) => {
+                    (self.on_outcome)(&location, &command, outcome)?
+                }
+                Entry::Ran(location, _, Err(e)) if self.mode == Mode::Lenient => {
+                    self.errors.push(RuntimeError(location, e).into())
+                }
+                Entry::Ran(location, _, Err(e)) => return Err(RuntimeError(location, e).into()),
+                Entry::Failed(e @ Error::Parse(_)) if self.mode == Mode::Lenient => {
+                    self.errors.push(e)
+                }
+                Entry::Failed(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// the shard that runs the commands of `client`
+fn shard_of(client: ClientId, shards: usize) -> usize {
+    client as usize % shards
+}
+
+// checks the part of a command that depends on the transactions of other clients, which a shard cannot see
+fn route(
+    owners: &mut HashMap<TransactionId, ClientId>,
+    limits: &AmountLimits,
+    command: &PaymentCommand,
+) -> Result<(), EngineError> {
+    match *command {
+        PaymentCommand::Deposit { client, tx, .. }
+        | PaymentCommand::Withdrawal { client, tx, .. } => {
+            // an invalid amount is refused before the id is looked at, as in a sequential run
+            limits.check(command).map_err(EngineError::InvalidAmount)?;
+
+            // the id is taken even if the shard declines the transaction
+            match owners.entry(tx) {
+                MapEntry::Occupied(_) => Err(EngineError::DuplicateTransaction(tx)),
+                MapEntry::Vacant(entry) => {
+                    entry.insert(client);
+                    Ok(())
+                }
+            }
+        }
+        PaymentCommand::Dispute { client, tx }
+        | PaymentCommand::Resolve { client, tx }
+        | PaymentCommand::Chargeback { client, tx } => match owners.get(&tx) {
+            Some(owner) if *owner != client => Err(EngineError::ClientIdMismatch(client, *owner)),
+            _ => Ok(()),
+        },
+    }
+}
+
+// runs the commands of a single shard in order, noting the position of the command that created each account
+fn run_shard(
+    mut engine: PaymentsEngine,
+    mode: Mode,
+    jobs: Receiver<Vec<Job>>,
+    results: Sender<Vec<(usize, Entry)>>,
+) -> (PaymentsEngine, Vec<(usize, ClientId)>) {
+    let mut created = Vec::new();
+
+    for batch in jobs {
+        let mut entries = Vec::with_capacity(batch.len());
+        let mut failed = false;
+
+        for (position, location, command) in batch {
+            let client = command.client();
+            let new = engine.get_account(client).is_ok_and(|x| x.is_none());
+
+            let result = engine.run_command(command);
+
+            if new && engine.get_account(client).is_ok_and(|x| x.is_some()) {
+                created.push((position, client));
+            }
+
+            failed = result.is_err() && mode == Mode::Strict;
+            entries.push((position, Entry::Ran(location, command, result)));
+
+            // a strict run ends at its first error, so nothing after it is run
+            if failed {
+                break;
+            }
+        }
+
+        // the reader stops listening once the run has ended
+        if results.send(entries).is_err() || failed {
+            break;
+        }
+    }
+
+    (engine, created)
+}
+
+// hands the commands read so far to their shards
+fn flush(batches: &mut [Vec<Job>], senders: &[SyncSender<Vec<Job>>]) {
+    for (batch, sender) in batches.iter_mut().zip(senders) {
+        if !batch.is_empty() {
+            // a shard only stops listening once the run has ended
+            let _ = sender.send(std::mem::take(batch));
+        }
+    }
+}
+
+// reads the commands and hands them to the shards, dropping the senders once every command has been read
+fn dispatch<I, F>(
+    commands: I,
+    mut owners: HashMap<TransactionId, ClientId>,
+    limits: AmountLimits,
+    senders: Vec<SyncSender<Vec<Job>>>,
+    results: Receiver<Vec<(usize, Entry)>>,
+    ordered: &mut Ordered<F>,
+) -> Result<(), Error>
+where
+    I: IntoIterator<Item = Result<(Location, PaymentCommand), Error>>,
+    F: FnMut(&Location, &PaymentCommand, CommandOutcome) -> Result<(), Error>,
+{
+    let mut batches = vec![Vec::new(); senders.len()];
+    let mut queued = 0;
+
+    for (position, command) in commands.into_iter().enumerate() {
+        let entry = match command {
+            Ok((location, command)) => match route(&mut owners, &limits, &command) {
+                Ok(()) => {
+                    batches[shard_of(command.client(), senders.len())]
+                        .push((position, location, command));
+                    queued += 1;
+                    None
+                }
+                Err(e) => Some(Entry::Ran(location, command, Err(e))),
+            },
+            Err(e) => Some(Entry::Failed(e)),
+        };
+
+        // no command after an error that ends the run is read, as in a sequential run
+        let ends_run = match &entry {
+            Some(Entry::Ran(..)) => ordered.mode == Mode::Strict,
+            Some(Entry::Failed(Error::Parse(_))) => ordered.mode == Mode::Strict,
+            Some(Entry::Failed(_)) => true,
+            None => false,
+        };
+
+        if let Some(entry) = entry {
+            ordered.pending.insert(position, entry);
+        }
+
+        if ends_run {
+            break;
+        }
+
+        // every shard is flushed at once, so that no entry waits long on a shard with few commands
+        if queued == BATCH {
+            flush(&mut batches, &senders);
+            queued = 0;
+
+            ordered.pending.extend(results.try_iter().flatten());
+            ordered.drain()?;
+        }
+    }
+
+    flush(&mut batches, &senders);
+    drop(senders);
+
+    // the shards finish once every batch has been run
+    for entries in results {
+        ordered.pending.extend(entries);
+        ordered.drain()?;
+    }
+
+    ordered.drain()
+}
+
+impl PaymentsEngine {
+    /// Runs payment commands like `PaymentsEngine::run_commands_with`, but across `threads` worker threads.
+    ///
+    /// Every command only changes the account of its own client, so the accounts are split into shards by client id, each with the transactions of its clients, and each shard is run by its own thread.
+    /// The commands of each client are run in the order they were read, and the checks that span clients, duplicate transaction ids and disputes naming another client's transaction, are made as the commands are read.
+    /// `on_outcome` is called, and the errors of a lenient run are returned, in the order the commands were read, so the accounts, outcomes and errors are the same as those of a sequential run.
+    ///
+    /// When a strict run fails, the error returned is the first in the order the commands were read, but the accounts may already hold later commands of other clients.
+    ///
+    /// # Errors
+    ///
+    /// Will return the same errors as `PaymentsEngine::run_commands_with`, or an `Error::Other` if a journal is open, as commands cannot be journaled when run in parallel.
+    pub fn run_commands_parallel<I, F>(
+        &mut self,
+        commands: I,
+        threads: NonZeroUsize,
+        mode: Mode,
+        on_outcome: F,
+    ) -> Result<Vec<Error>, Error>
+    where
+        I: IntoIterator<Item = Result<(Location, PaymentCommand), Error>>,
+        F: FnMut(&Location, &PaymentCommand, CommandOutcome) -> Result<(), Error>,
+    {
+        if self.journal.is_some() {
+            return Err(Error::Other(
+                "Commands cannot be journaled when run in parallel".to_string(),
+            ));
+        }
+
+        let threads = threads.get();
+        let config = *self.config();
+        let journaled = self.journaled;
+        let (accounts, transactions) = std::mem::take(self).into_parts();
+
+        // the client of every transaction, for the checks that span shards
+        let owners = transactions
+            .iter()
+            .map(|(tx, data)| (*tx, data.client()))
+            .collect();
+        let existing: Vec<ClientId> = accounts.iter().map(|(id, _)| *id).collect();
+
+        let mut shards = vec![(Vec::new(), HashMap::new()); threads];
+        for (id, data) in accounts {
+            shards[shard_of(id, threads)].0.push((id, data));
+        }
+        for (tx, data) in transactions {
+            shards[shard_of(data.client(), threads)].1.insert(tx, data);
+        }
+
+        let mut ordered = Ordered {
+            pending: BTreeMap::new(),
+            next: 0,
+            mode,
+            errors: Vec::new(),
+            on_outcome,
+        };
+
+        let (result, shards) = thread::scope(|scope| {
+            let (results_sender, results) = mpsc::channel();
+
+            let (senders, handles): (Vec<_>, Vec<_>) = shards
+                .into_iter()
+                .map(|(accounts, transactions)| {
+                    let (sender, jobs) = mpsc::sync_channel(QUEUE);
+                    let engine = PaymentsEngine::from_parts(config, accounts, transactions);
+                    let results = results_sender.clone();
+
+                    (
+                        sender,
+                        scope.spawn(move || run_shard(engine, mode, jobs, results)),
+                    )
+                })
+                .collect();
+            drop(results_sender);
+
+            let result = dispatch(
+                commands,
+                owners,
+                config.amount_limits,
+                senders,
+                results,
+                &mut ordered,
+            );
+
+            let shards: Vec<_> = handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|e| std::panic::resume_unwind(e))
+                })
+                .collect();
+
+            (result, shards)
+        });
+
+        // put the shards back together, keeping the accounts in the order they were created in
+        let mut clients: HashMap<ClientId, ClientData> = HashMap::new();
+        let mut transactions: HashMap<TransactionId, TransactionData> = HashMap::new();
+        let mut created = Vec::new();
+
+        for (engine, shard_created) in shards {
+            let (accounts, shard_transactions) = engine.into_parts();
+
+            clients.extend(accounts);
+            transactions.extend(shard_transactions);
+            created.extend(shard_created);
+        }
+
+        created.sort_unstable();
+
+        let accounts = existing
+            .into_iter()
+            .chain(created.into_iter().map(|(_, id)| id))
+            .filter_map(|id| Some((id, clients.remove(&id)?)))
+            .collect();
+
+        *self = PaymentsEngine::from_parts(config, accounts, transactions);
+        self.journaled = journaled;
+
+        result.map(|()| ordered.errors)
+    }
+}
@@ -1,113 +1,295 @@
+use std::{
+    borrow::Cow,
+    io::{self, BufRead, Read},
+    num::{IntErrorKind, ParseIntError},
+    str::FromStr,
+    sync::Arc,
+};
+
 use crate::{common::*, error::*};
 
+// parses a client or transaction id, separating ids that are too large to represent from malformed ones
+fn parse_id<T: FromStr<Err = ParseIntError>>(
+    s: &str,
+    name: &str,
+) -> Result<T, PaymentCommandParseError> {
+    str::parse::<T>(s).map_err(|e| match e.kind() {
+        IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => {
+            PaymentCommandParseError::IdOutOfRange(format!(
+                "{} {} does not fit in {} bits",
+                name,
+                s,
+                std::mem::size_of::<T>() * 8
+            ))
+        }
+        _ => PaymentCommandParseError::ParseError(format!("Could not parse {}: {}", name, e)),
+    })
+}
+
+// a negative or zero amount would turn a deposit into a withdrawal, or vice versa
+fn check_positive(amount: Amount) -> Result<(), PaymentCommandParseError> {
+    if amount <= Amount::ZERO {
+        return Err(PaymentCommandParseError::InvalidAmount(format!(
+            "{} is not a positive amount",
+            amount
+        )));
+    }
+
+    Ok(())
+}
+
+/// Splits a single CSV record into its fields, following RFC 4180.
+///
+/// Fields may be quoted, in which case they can contain commas, line breaks, and quotes escaped by doubling them.
+/// Whitespace around fields, and around the quotes of quoted fields, is trimmed.
+///
+/// # Errors
+///
+/// - `ParseError`: when a quoted field is never closed, or is followed by anything but a comma.
+pub fn split_record(s: &str) -> Result<Vec<Cow<'_, str>>, PaymentCommandParseError> {
+    let bytes = s.as_bytes();
+    let is_space = |b: Option<&u8>| matches!(b, Some(b' ' | b'\t'));
+
+    let mut fields = Vec::new();
+    let mut i = 0;
+
+    loop {
+        // skip whitespace before the field
+        while is_space(bytes.get(i)) {
+            i += 1;
+        }
+
+        if bytes.get(i) != Some(&b'"') {
+            // unquoted fields run until the next comma
+            let start = i;
+            while i < bytes.len() && bytes[i] != b',' {
+                i += 1;
+            }
+
+            fields.push(Cow::Borrowed(s[start..i].trim()));
+
+            if i == bytes.len() {
+                return Ok(fields);
+            }
+
+            i += 1;
+            continue;
+        }
+
+        // quoted fields run until the next quote that is not doubled
+        let start = i + 1;
+        let mut escaped = false;
+        i = start;
+
+        loop {
+            match bytes.get(i) {
+                None => {
+                    return Err(PaymentCommandParseError::ParseError(
+                        "Unterminated quoted field.".to_string(),
+                    ))
+                }
+                Some(b'"') if bytes.get(i + 1) == Some(&b'"') => {
+                    escaped = true;
+                    i += 2;
+                }
+                Some(b'"') => break,
+                Some(_) => i += 1,
+            }
+        }
+
+        let field = &s[start..i];
+        fields.push(if escaped {
+            Cow::Owned(field.replace("\"\"", "\""))
+        } else {
+            Cow::Borrowed(field)
+        });
+
+        // skip the closing quote and any whitespace after it
+        i += 1;
+        while is_space(bytes.get(i)) {
+            i += 1;
+        }
+
+        match bytes.get(i) {
+            None => return Ok(fields),
+            Some(b',') => i += 1,
+            Some(_) => {
+                return Err(PaymentCommandParseError::ParseError(
+                    "Unexpected data after a quoted field.".to_string(),
+                ))
+            }
+        }
+    }
+}
+
 impl PaymentCommand {
-    // using csv would be redundant when the parsing is this easy
-    /// Parses a payment command from a single row of a CSV file.
+    /// Parses a payment command from a single line of a CSV file.
+    ///
+    /// # Errors
+    ///
+    /// Will return any error from `split_record` or `PaymentCommand::from_csv_record`.
+    pub fn from_csv_line(s: &str) -> Result<Self, PaymentCommandParseError> {
+        Self::from_csv_record(&split_record(s)?)
+    }
+
+    /// Parses a payment command from the fields of a single CSV record, with the columns in the order `type,client,tx,amount`.
+    ///
+    /// # Errors
+    ///
+    /// Will return any error from `ColumnMap::parse`.
+    pub fn from_csv_record<S: AsRef<str>>(record: &[S]) -> Result<Self, PaymentCommandParseError> {
+        ColumnMap::default().parse(record)
+    }
+}
+
+/// Maps the columns of a CSV file to the fields of a payment command, by their name in the header.
+///
+/// The `type`, `client`, `tx` and `amount` columns are required, but may appear in any order. Column names are matched ignoring case and surrounding whitespace, and any other columns are ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnMap {
+    kind: usize,
+    client: usize,
+    tx: usize,
+    amount: usize,
+    extra: Vec<String>,
+}
+
+impl Default for ColumnMap {
+    /// The columns in the order `type,client,tx,amount`, with no extra columns.
+    fn default() -> Self {
+        Self {
+            kind: 0,
+            client: 1,
+            tx: 2,
+            amount: 3,
+            extra: Vec::new(),
+        }
+    }
+}
+
+impl ColumnMap {
+    /// Creates a `ColumnMap` from the fields of a CSV header.
+    ///
+    /// If a column appears more than once, its first occurrence is used.
+    ///
+    /// # Errors
+    ///
+    /// - `MissingHeader`: when a required column is absent, naming the first one missing.
+    pub fn from_header<S: AsRef<str>>(header: &[S]) -> Result<Self, PaymentCommandParseError> {
+        let position = |name: &str| {
+            header
+                .iter()
+                .position(|x| x.as_ref().trim().eq_ignore_ascii_case(name))
+                .ok_or_else(|| {
+                    PaymentCommandParseError::MissingHeader(format!(
+                        "The CSV header has no {} column.",
+                        name
+                    ))
+                })
+        };
+
+        let kind = position("type")?;
+        let client = position("client")?;
+        let tx = position("tx")?;
+        let amount = position("amount")?;
+
+        let extra = header
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| ![kind, client, tx, amount].contains(i))
+            .map(|(_, x)| x.as_ref().trim().to_string())
+            .collect();
+
+        Ok(Self {
+            kind,
+            client,
+            tx,
+            amount,
+            extra,
+        })
+    }
+
+    /// Returns the names of the columns that are not used by any payment command, in the order they appear in the header.
+    pub fn extra_columns(&self) -> &[String] {
+        &self.extra
+    }
+
+    /// Parses a payment command from the fields of a single CSV record.
+    ///
+    /// An empty amount is treated the same as a missing one, so dispute rows with a trailing comma are accepted.
     ///
     /// # Errors
     ///
     /// - `MissingData`: when the row is missing a required datum.
     /// - `ParseError`: when a cell cannot be parsed into a numerical type.
+    /// - `IdOutOfRange`: when a client or transaction id is too large for `ClientId` or `TransactionId`.
+    /// - `InvalidAmount`: when the amount of a deposit or withdrawal is negative or zero, too large, or a number that is not a decimal, such as `NaN`, `inf` or `1e308`.
     /// - `NoSuchPaymentCommand`: when the `type` cell contains an invalid command.
-    ///
-    /// # Panics
-    ///
-    /// This function, in theory, should never panic.
-    pub fn from_csv_line(s: &str) -> Result<Self, PaymentCommandParseError> {
-        let split_string = s.split(',').map(str::trim).collect::<Vec<&str>>();
+    pub fn parse<S: AsRef<str>>(
+        &self,
+        record: &[S],
+    ) -> Result<PaymentCommand, PaymentCommandParseError> {
+        let cell = |i: usize| record.get(i).map(|x| x.as_ref().trim());
+        let missing_data =
+            || PaymentCommandParseError::MissingData("Too little data in the row.".to_string());
 
-        if split_string.len() < 3 {
-            return Err(PaymentCommandParseError::MissingData(
-                "Too little data in the row.".to_string(),
-            ));
+        let (kind, client, tx) = match (cell(self.kind), cell(self.client), cell(self.tx)) {
+            (Some(kind), Some(client), Some(tx)) => (kind, client, tx),
+            _ => return Err(missing_data()),
         };
 
-        match *split_string.get(0).unwrap() {
-            "deposit" if split_string.len() < 4 => Err(PaymentCommandParseError::MissingData(
-                "Too little data in the row.".to_string(),
-            )),
-            "deposit" => Ok(Self::Deposit {
-                client: str::parse::<ClientId>(split_string[1]).map_err(|e| {
-                    PaymentCommandParseError::ParseError(format!(
-                        "Could not parse client id: {}",
-                        e
-                    ))
-                })?,
-                tx: str::parse::<TransactionId>(split_string[2]).map_err(|e| {
-                    PaymentCommandParseError::ParseError(format!(
-                        "Could not parse transaction id: {}",
-                        e
+        // an empty trailing amount is the same as no amount
+        let amount = cell(self.amount).filter(|x| !x.is_empty());
+        let parse_amount = || {
+            let text = amount.ok_or_else(missing_data)?;
+            let amount = str::parse::<Amount>(text).map_err(|e| match e {
+                // numbers no amount can hold, such as `NaN`, `inf` or `1e308`, are invalid amounts rather than unreadable cells
+                AmountParseError::Overflow => PaymentCommandParseError::InvalidAmount(format!(
+                    "{} is too large to represent",
+                    text
+                )),
+                AmountParseError::InvalidDigit if text.parse::<f64>().is_ok() => {
+                    PaymentCommandParseError::InvalidAmount(format!(
+                        "{} is not a decimal amount",
+                        text
                     ))
-                })?,
-                amount: str::parse::<f64>(split_string[3]).map_err(|e| {
-                    PaymentCommandParseError::ParseError(format!("Could not parse amount: {}", e))
-                })?,
+                }
+                e => PaymentCommandParseError::ParseError(format!("Could not parse amount: {}", e)),
+            })?;
+
+            check_positive(amount)?;
+
+            Ok(amount)
+        };
+
+        match kind {
+            "deposit" | "withdrawal" if amount.is_none() => Err(missing_data()),
+
+            "deposit" => Ok(PaymentCommand::Deposit {
+                client: parse_id::<ClientId>(client, "client id")?,
+                tx: parse_id::<TransactionId>(tx, "transaction id")?,
+                amount: parse_amount()?,
             }),
 
-            "withdrawal" if split_string.len() < 4 => Err(PaymentCommandParseError::MissingData(
-                "Too little data in the row.".to_string(),
-            )),
-            "withdrawal" => Ok(Self::Withdrawal {
-                client: str::parse::<ClientId>(split_string[1]).map_err(|e| {
-                    PaymentCommandParseError::ParseError(format!(
-                        "Could not parse client id: {}",
-                        e
-                    ))
-                })?,
-                tx: str::parse::<TransactionId>(split_string[2]).map_err(|e| {
-                    PaymentCommandParseError::ParseError(format!(
-                        "Could not parse transaction id: {}",
-                        e
-                    ))
-                })?,
-                amount: str::parse::<f64>(split_string[3]).map_err(|e| {
-                    PaymentCommandParseError::ParseError(format!("Could not parse amount: {}", e))
-                })?,
+            "withdrawal" => Ok(PaymentCommand::Withdrawal {
+                client: parse_id::<ClientId>(client, "client id")?,
+                tx: parse_id::<TransactionId>(tx, "transaction id")?,
+                amount: parse_amount()?,
             }),
 
-            "dispute" => Ok(Self::Dispute {
-                client: str::parse::<ClientId>(split_string[1]).map_err(|e| {
-                    PaymentCommandParseError::ParseError(format!(
-                        "Could not parse client id: {}",
-                        e
-                    ))
-                })?,
-                tx: str::parse::<TransactionId>(split_string[2]).map_err(|e| {
-                    PaymentCommandParseError::ParseError(format!(
-                        "Could not parse transaction id: {}",
-                        e
-                    ))
-                })?,
+            "dispute" => Ok(PaymentCommand::Dispute {
+                client: parse_id::<ClientId>(client, "client id")?,
+                tx: parse_id::<TransactionId>(tx, "transaction id")?,
             }),
 
-            "resolve" => Ok(Self::Resolve {
-                client: str::parse::<ClientId>(split_string[1]).map_err(|e| {
-                    PaymentCommandParseError::ParseError(format!(
-                        "Could not parse client id: {}",
-                        e
-                    ))
-                })?,
-                tx: str::parse::<TransactionId>(split_string[2]).map_err(|e| {
-                    PaymentCommandParseError::ParseError(format!(
-                        "Could not parse transaction id: {}",
-                        e
-                    ))
-                })?,
+            "resolve" => Ok(PaymentCommand::Resolve {
+                client: parse_id::<ClientId>(client, "client id")?,
+                tx: parse_id::<TransactionId>(tx, "transaction id")?,
             }),
 
-            "chargeback" => Ok(Self::Chargeback {
-                client: str::parse::<ClientId>(split_string[1]).map_err(|e| {
-                    PaymentCommandParseError::ParseError(format!(
-                        "Could not parse client id: {}",
-                        e
-                    ))
-                })?,
-                tx: str::parse::<TransactionId>(split_string[2]).map_err(|e| {
-                    PaymentCommandParseError::ParseError(format!(
-                        "Could not parse transaction id: {}",
-                        e
-                    ))
-                })?,
+            "chargeback" => Ok(PaymentCommand::Chargeback {
+                client: parse_id::<ClientId>(client, "client id")?,
+                tx: parse_id::<TransactionId>(tx, "transaction id")?,
             }),
 
             s => Err(PaymentCommandParseError::NoSuchPaymentCommand(format!(
@@ -118,39 +300,304 @@ impl PaymentCommand {
     }
 }
 
-// error returns the original error and the line on which it occurred
-/// Parses a vector of commands from a CSV string.
+/// Limits on the amount of a single deposit or withdrawal, checked as each command is read, and again as it is run.
 ///
-/// # Errors
-///
-/// This function will return any error that it encounters when calling `PaymentCommand::from_csv_line`.
-///
-/// In addition, this function will return a `PaymentCommandParseError::MissingHeader` if it does not read the correct header.
-pub fn parse_commands(s: &str) -> Result<Vec<PaymentCommand>, ParseError> {
-    let mut commands = Vec::new();
+/// Both bounds are inclusive, and amounts must always be positive regardless of `min`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AmountLimits {
+    pub min: Option<Amount>,
+    pub max: Option<Amount>,
+}
+
+impl AmountLimits {
+    /// Checks the amount of a deposit or withdrawal against the limits. Other commands always pass.
+    ///
+    /// # Errors
+    ///
+    /// - `InvalidAmount`: when the amount is not positive, or is below `min` or above `max`.
+    pub fn check(&self, command: &PaymentCommand) -> Result<(), PaymentCommandParseError> {
+        let Some(amount) = command.amount() else {
+            return Ok(());
+        };
+
+        check_positive(amount)?;
+
+        if let Some(min) = self.min.filter(|min| amount < *min) {
+            return Err(PaymentCommandParseError::InvalidAmount(format!(
+                "{} is below the minimum of {}",
+                amount, min
+            )));
+        }
+
+        if let Some(max) = self.max.filter(|max| amount > *max) {
+            return Err(PaymentCommandParseError::InvalidAmount(format!(
+                "{} is above the maximum of {}",
+                amount, max
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+// the most bytes a single record may take up, far more than any command needs, so neither a quote that is never closed nor a line that never ends can exhaust memory
+pub(crate) const MAX_RECORD: usize = 64 * 1024;
+
+// whether a line of a record ends inside a quoted field, given whether it starts inside one, following `split_record`
+fn ends_quoted(line: &str, mut quoted: bool) -> bool {
+    let mut bytes = line.bytes().peekable();
+    // only a quote at the start of a field opens a quoted field, elsewhere it is part of the text
+    let mut field_start = !quoted;
+
+    while let Some(b) = bytes.next() {
+        match b {
+            b'"' if quoted => quoted = bytes.next_if_eq(&b'"').is_some(),
+            b'"' if field_start => {
+                quoted = true;
+                field_start = false;
+            }
+            b',' if !quoted => field_start = true,
+            b' ' | b'\t' => {}
+            _ => field_start = false,
+        }
+    }
+
+    quoted
+}
+
+// assembles records from the lines of a CSV stream, and parses them into commands, for both `CommandReader` and the async reader
+#[derive(Debug, Default)]
+pub(crate) struct Records {
+    file: Option<Arc<str>>,
+    line: usize,
+    offset: u64,
+    record: String,
+    // where the record being assembled starts, while a quoted field spans several lines
+    start: Option<Location>,
+    columns: ColumnMap,
+    limits: AmountLimits,
+}
+
+impl Records {
+    pub(crate) fn new(file: Option<Arc<str>>, limits: AmountLimits) -> Self {
+        Self {
+            file,
+            limits,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn columns(&self) -> &ColumnMap {
+        &self.columns
+    }
+
+    fn location(&self, line: usize, offset: u64) -> Location {
+        Location {
+            file: self.file.clone(),
+            line,
+            offset,
+        }
+    }
+
+    // takes the next line, along with its line ending, returning the location of the record once it is complete
+    //
+    // a line longer than `MAX_RECORD` may be cut short by the reader, which then discards the rest of it with `Records::skip`
+    pub(crate) fn push_line(&mut self, line: &[u8]) -> Result<Option<Location>, Error> {
+        let location = self.location(self.line + 1, self.offset);
+
+        self.line += 1;
+        self.offset += line.len() as u64;
+
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        // the byte order mark can only appear at the very start of the stream
+        let line = match self.line {
+            1 => line.strip_prefix("\u{feff}".as_bytes()).unwrap_or(line),
+            _ => line,
+        };
 
-    let mut iter = s.lines().enumerate();
+        // a record is only continued on the next line while one of its quoted fields is open
+        let continued = self.start.is_some();
 
-    if let Some((_, line)) = iter.next() {
-        let split_line = line.split(',').map(str::trim).collect::<Vec<_>>();
+        let start = match self.start.take() {
+            Some(start) => {
+                self.record.push('\n');
+                start
+            }
+            // blank and comment lines are told apart by their first line alone, so a quote in a comment is never continued
+            None if matches!(line.trim_ascii_start().first(), None | Some(b'#')) => {
+                return Ok(None)
+            }
+            None => {
+                self.record.clear();
+                location
+            }
+        };
 
-        if split_line[..] != ["type", "client", "tx", "amount"] {
+        if self.record.len() + line.len() > MAX_RECORD {
             return Err(ParseError(
-                0,
-                PaymentCommandParseError::MissingHeader("Incorrect CSV header.".to_string()),
-            ));
+                start,
+                PaymentCommandParseError::ParseError(format!(
+                    "The record is longer than {} bytes.",
+                    MAX_RECORD
+                )),
+            )
+            .into());
+        }
+
+        let line = std::str::from_utf8(line).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stream did not contain valid UTF-8",
+            )
+        })?;
+
+        self.record.push_str(line);
+
+        // a quoted field can span several lines, so the record carries on until it is closed
+        if ends_quoted(line, continued) {
+            self.start = Some(start);
+            return Ok(None);
         }
-    } else {
-        return Err(ParseError(
-            0,
-            PaymentCommandParseError::MissingHeader("Missing CSV header.".to_string()),
-        ));
+
+        Ok(Some(start))
+    }
+
+    // counts the rest of an overlong line, which the reader discards
+    pub(crate) fn skip(&mut self, bytes: usize) {
+        self.offset += bytes as u64;
     }
 
-    for (i, line) in iter {
-        let command = PaymentCommand::from_csv_line(line).map_err(|e| ParseError(i + 1, e))?;
-        commands.push(command);
+    // ends the stream, returning where a record left open by an unterminated quote starts, which `split_record` then reports
+    pub(crate) fn finish(&mut self) -> Option<Location> {
+        self.start.take()
     }
 
-    Ok(commands)
+    // maps the columns from the header, given the location of the first record, if there is one
+    pub(crate) fn header(&mut self, location: Option<Location>) -> Result<(), Error> {
+        // an empty input has no line to point to, so the error is reported at line 0
+        let location = location.ok_or_else(|| {
+            ParseError(
+                self.location(0, 0),
+                PaymentCommandParseError::MissingHeader("Missing CSV header.".to_string()),
+            )
+        })?;
+
+        self.columns = split_record(&self.record)
+            .and_then(|header| ColumnMap::from_header(&header))
+            .map_err(|e| ParseError(location, e))?;
+
+        Ok(())
+    }
+
+    // parses the last complete record into a command
+    pub(crate) fn command(&self, location: Location) -> Result<(Location, PaymentCommand), Error> {
+        split_record(&self.record)
+            .and_then(|record| self.columns.parse(&record))
+            .and_then(|command| self.limits.check(&command).map(|_| command))
+            .map(|command| (location.clone(), command))
+            .map_err(|e| ParseError(location, e).into())
+    }
+}
+
+/// Reads payment commands from a CSV stream one record at a time, so memory use does not grow with the size of the input.
+///
+/// Records follow RFC 4180, see `split_record`. Lines may end with either LF or CRLF, a leading UTF-8 byte order mark is ignored, and blank lines and lines starting with `#` are skipped.
+/// A record may take up at most 64 KiB, so a quoted field that is never closed is reported as a `ParseError` rather than read to the end of the input.
+///
+/// Columns are mapped by the names in the header, see `ColumnMap`.
+///
+/// Each item is the parsed command along with the location its record starts at.
+pub struct CommandReader<R: BufRead> {
+    reader: R,
+    line: Vec<u8>,
+    records: Records,
+}
+
+impl<R: BufRead> CommandReader<R> {
+    /// Creates a new `CommandReader`, reading and checking the CSV header.
+    ///
+    /// # Errors
+    ///
+    /// Will return a `PaymentCommandParseError::MissingHeader` if the header is absent or lacks a required column, or any IO error from reading the header.
+    pub fn new(reader: R) -> Result<Self, Error> {
+        Self::open(reader, None, AmountLimits::default())
+    }
+
+    /// Creates a new `CommandReader` that rejects deposits and withdrawals outside of `limits`, reading and checking the CSV header.
+    ///
+    /// # Errors
+    ///
+    /// Will return any error from reading the header, see `CommandReader::new`.
+    pub fn with_limits(reader: R, limits: AmountLimits) -> Result<Self, Error> {
+        Self::open(reader, None, limits)
+    }
+
+    /// Creates a new `CommandReader` like `CommandReader::with_limits`, naming `file` in the location of every command and error.
+    ///
+    /// # Errors
+    ///
+    /// Will return any error from reading the header, see `CommandReader::new`.
+    pub fn with_file(reader: R, file: &str, limits: AmountLimits) -> Result<Self, Error> {
+        Self::open(reader, Some(file.into()), limits)
+    }
+
+    fn open(reader: R, file: Option<Arc<str>>, limits: AmountLimits) -> Result<Self, Error> {
+        let mut command_reader = Self {
+            reader,
+            line: Vec::new(),
+            records: Records::new(file, limits),
+        };
+
+        let location = command_reader.read_record()?;
+        command_reader.records.header(location)?;
+
+        Ok(command_reader)
+    }
+
+    /// Returns the column mapping read from the header.
+    pub fn columns(&self) -> &ColumnMap {
+        self.records.columns()
+    }
+
+    // reads the next record, skipping blank and comment lines, returning the location it starts at
+    fn read_record(&mut self) -> Result<Option<Location>, Error> {
+        loop {
+            self.line.clear();
+
+            // one byte past `MAX_RECORD` is enough to tell an overlong line apart
+            let limit = MAX_RECORD as u64 + 1;
+            if (&mut self.reader)
+                .take(limit)
+                .read_until(b'\n', &mut self.line)?
+                == 0
+            {
+                return Ok(self.records.finish());
+            }
+
+            let location = self.records.push_line(&self.line);
+
+            // the rest of an overlong line is discarded, so reading carries on from the next line
+            if self.line.len() > MAX_RECORD && !self.line.ends_with(b"\n") {
+                let skipped = self.reader.skip_until(b'\n')?;
+                self.records.skip(skipped);
+            }
+
+            if let Some(location) = location? {
+                return Ok(Some(location));
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for CommandReader<R> {
+    type Item = Result<(Location, PaymentCommand), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_record() {
+            Ok(location) => Some(self.records.command(location?)),
+            Err(e) => Some(Err(e)),
+        }
+    }
 }
@@ -0,0 +1,156 @@
+use std::{borrow::Cow, io::Write};
+
+use crate::{
+    common::*,
+    engine::{CommandOutcome, IgnoreReason},
+    error::*,
+};
+
+// quotes a field if it contains a character that would otherwise break the CSV record, see `crate::split_record`
+pub(crate) fn escape_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Writes a CSV log of every command that was ignored, and of every row skipped in `Mode::Lenient`, and why, so that no row is dropped silently.
+///
+/// Each row names the file and line the command was read from, the file being empty for commands not read from one.
+pub struct RejectionWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> RejectionWriter<W> {
+    /// Creates a new `RejectionWriter`, writing the CSV header.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from writing the header.
+    pub fn new(mut writer: W) -> Result<Self, Error> {
+        writer.write_all(b"file,line,tx,client,reason\n")?;
+
+        Ok(Self { writer })
+    }
+
+    /// Writes a single rejected command.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from writing the row.
+    pub fn write(
+        &mut self,
+        location: &Location,
+        command: &PaymentCommand,
+        reason: IgnoreReason,
+    ) -> Result<(), Error> {
+        writeln!(
+            self.writer,
+            "{},{},{},{},{reason}",
+            escape_field(location.file.as_deref().unwrap_or_default()),
+            location.line,
+            command.tx(),
+            command.client()
+        )?;
+
+        Ok(())
+    }
+
+    /// Writes a single row skipped in `Mode::Lenient`, with its error as the reason, along with its command if the row could be parsed.
+    ///
+    /// The tx and client are left empty for rows that could not be parsed.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from writing the row.
+    pub fn write_error(
+        &mut self,
+        error: &Error,
+        command: Option<&PaymentCommand>,
+    ) -> Result<(), Error> {
+        let (location, reason) = match error {
+            Error::Runtime(RuntimeError(location, e)) => (Some(location), e.to_string()),
+            Error::Parse(ParseError(location, e)) => (Some(location), e.to_string()),
+            e => (None, e.to_string()),
+        };
+        let (tx, client) = command
+            .map(|x| (x.tx().to_string(), x.client().to_string()))
+            .unwrap_or_default();
+
+        writeln!(
+            self.writer,
+            "{},{},{tx},{client},{}",
+            escape_field(location.and_then(|x| x.file.as_deref()).unwrap_or_default()),
+            location.map(|x| x.line.to_string()).unwrap_or_default(),
+            escape_field(&reason)
+        )?;
+
+        Ok(())
+    }
+
+    /// Flushes the underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from flushing.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Writes a CSV log of every command that was run, along with its outcome, such as the history of a single client.
+pub struct HistoryWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> HistoryWriter<W> {
+    /// Creates a new `HistoryWriter`, writing the CSV header.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from writing the header.
+    pub fn new(mut writer: W) -> Result<Self, Error> {
+        writer.write_all(b"file,line,type,client,tx,amount,outcome\n")?;
+
+        Ok(Self { writer })
+    }
+
+    /// Writes a single command and its outcome. The amount is left empty for commands without one.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from writing the row.
+    pub fn write(
+        &mut self,
+        location: &Location,
+        command: &PaymentCommand,
+        outcome: CommandOutcome,
+    ) -> Result<(), Error> {
+        writeln!(
+            self.writer,
+            "{},{},{},{},{},{},{outcome}",
+            escape_field(location.file.as_deref().unwrap_or_default()),
+            location.line,
+            command.name(),
+            command.client(),
+            command.tx(),
+            command.amount().map(|x| x.to_string()).unwrap_or_default(),
+        )?;
+
+        Ok(())
+    }
+
+    /// Flushes the underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from flushing.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    engine::{Mode, PaymentsEngine},
+    error::*,
+    http,
+    output::{AccountOrder, OutputFormat},
+    parse::{CommandReader, MAX_RECORD},
+};
+
+/// The first line a connection sends to have the current accounts written back, instead of streaming commands.
+pub const ACCOUNTS_QUERY: &str = "accounts";
+
+// connections only parse and run one row at a time, so their threads need far less than the default stack
+const STACK_SIZE: usize = 128 * 1024;
+
+// the most of the rest of a failed connection's input that is discarded before it is closed
+const DRAIN: u64 = 1024 * 1024;
+
+/// Settings of a `Server`, shared by every connection.
+#[derive(Debug, Clone, Copy)]
+pub struct ServerConfig {
+    /// Whether a connection stops at its first error or skips the offending rows.
+    pub mode: Mode,
+    /// The format the accounts are written in, for `ACCOUNTS_QUERY`.
+    pub format: OutputFormat,
+    /// The order the accounts are written in, for `ACCOUNTS_QUERY`.
+    pub order: AccountOrder,
+    /// How long a connection may wait to send or receive anything before it is dropped, so a peer that stalls cannot hold on to its thread.
+    /// It must not be zero. Defaults to 30 seconds.
+    pub timeout: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            mode: Mode::default(),
+            format: OutputFormat::default(),
+            order: AccountOrder::default(),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Runs the CSV commands streamed by any number of TCP connections into a single shared `PaymentsEngine`.
+///
+/// Each connection either sends `ACCOUNTS_QUERY` as its first line, and is sent the current accounts, or streams commands in the same format as a file, starting with a header.
+/// Once a connection has finished sending, it is sent the errors skipped in `Mode::Lenient`, one per line, followed by a `<peer>: <N> commands, <M> errors` summary.
+/// A connection that fails, such as at its first error in `Mode::Strict`, is instead sent only that error as soon as it happens, and is closed once up to 1 MiB more of its rows are discarded.
+/// Lines may take up at most 64 KiB, and a connection that sends or receives nothing for `ServerConfig::timeout` is dropped.
+///
+/// The same engine can also be served over HTTP, see `Server::serve_http`.
+///
+/// Commands lock the engine one at a time, so the commands of concurrent connections are interleaved, while those of each connection are run in the order they were sent.
+#[derive(Debug, Clone)]
+pub struct Server {
+    engine: Arc<Mutex<PaymentsEngine>>,
+    config: ServerConfig,
+}
+
+impl Server {
+    pub fn new(engine: PaymentsEngine, config: ServerConfig) -> Self {
+        Self {
+            engine: Arc::new(Mutex::new(engine)),
+            config,
+        }
+    }
+
+    /// Returns the engine shared by every connection.
+    pub fn engine(&self) -> Arc<Mutex<PaymentsEngine>> {
+        self.engine.clone()
+    }
+
+    /// Accepts connections on `listener` until it fails, serving each on its own thread.
+    ///
+    /// Connections that fail, or that cannot be accepted or given a thread, are passed to `on_error` without stopping the server.
+    pub fn serve<F>(&self, listener: TcpListener, on_error: F)
+    where
+        F: Fn(&Error) + Send + Sync + 'static,
+    {
+        self.accept(listener, handle, on_error);
+    }
+
+    /// Accepts HTTP connections on `listener` like `Server::serve`, serving a single request on each, see the README for the routes.
+    pub fn serve_http<F>(&self, listener: TcpListener, on_error: F)
+    where
+        F: Fn(&Error) + Send + Sync + 'static,
+    {
+        self.accept(listener, http::handle, on_error);
+    }
+
+    fn accept<F>(&self, listener: TcpListener, handle: Handler, on_error: F)
+    where
+        F: Fn(&Error) + Send + Sync + 'static,
+    {
+        let on_error = Arc::new(on_error);
+
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                // such as running out of file descriptors, which connections closing will free up
+                Err(e) => {
+                    on_error(&Error::Other(format!("Could not accept a connection: {e}")));
+                    continue;
+                }
+            };
+
+            let engine = self.engine.clone();
+            let config = self.config;
+            let thread_on_error = on_error.clone();
+
+            let spawned = thread::Builder::new()
+                .stack_size(STACK_SIZE)
+                .spawn(move || {
+                    if let Err(e) = handle(&engine, config, stream) {
+                        thread_on_error(&e);
+                    }
+                });
+
+            if let Err(e) = spawned {
+                on_error(&Error::Other(format!(
+                    "Could not start a thread for a connection: {e}"
+                )));
+            }
+        }
+    }
+}
+
+// serves a single connection, returning any error that stops it from being answered
+type Handler = fn(&Mutex<PaymentsEngine>, ServerConfig, TcpStream) -> Result<(), Error>;
+
+// a panic on another connection leaves the engine as it was after its last command, so it is still safe to use
+pub(crate) fn lock(engine: &Mutex<PaymentsEngine>) -> MutexGuard<'_, PaymentsEngine> {
+    engine.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// serves a single CSV connection
+fn handle(
+    engine: &Mutex<PaymentsEngine>,
+    config: ServerConfig,
+    stream: TcpStream,
+) -> Result<(), Error> {
+    let peer = stream
+        .peer_addr()
+        .map_or_else(|_| "<unknown peer>".to_string(), |x| x.to_string());
+
+    stream.set_read_timeout(Some(config.timeout))?;
+    stream.set_write_timeout(Some(config.timeout))?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut writer = BufWriter::new(&stream);
+
+    // an overlong first line is cut short here, and then rejected by the `CommandReader` as it reads on
+    let mut first = Vec::new();
+    (&mut reader)
+        .take(MAX_RECORD as u64)
+        .read_until(b'\n', &mut first)?;
+
+    if first.trim_ascii() == ACCOUNTS_QUERY.as_bytes() {
+        // the lock is released before writing, so a slow reader never holds up the other connections
+        let accounts = lock(engine).accounts_in_order(config.order)?;
+
+        return config.format.writer(writer).write_accounts(&accounts);
+    }
+
+    // the first line is part of the header, so it is read again along with the rest
+    let mut reader = Cursor::new(first).chain(reader);
+    let limits = lock(engine).config().amount_limits;
+
+    let result = CommandReader::with_file(&mut reader, &peer, limits)
+        .and_then(|mut commands| run(engine, config.mode, &mut commands));
+
+    match &result {
+        Ok((commands, errors)) => {
+            for e in errors {
+                writeln!(writer, "{e}")?;
+            }
+
+            writeln!(
+                writer,
+                "{peer}: {commands} commands, {} errors",
+                errors.len()
+            )?;
+        }
+        Err(e) => writeln!(writer, "{e}")?,
+    }
+
+    writer.flush()?;
+
+    // discard some of the rest of the rows, so the connection is not reset before the error is read, and close it
+    if result.is_err() {
+        // the error has been sent, so failing to discard the rows or to close the connection changes nothing
+        let _ = io::copy(&mut reader.take(DRAIN), &mut io::sink());
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+
+    Ok(())
+}
+
+// runs the commands of a connection, returning how many were run and the errors skipped in lenient mode
+fn run<R: BufRead>(
+    engine: &Mutex<PaymentsEngine>,
+    mode: Mode,
+    commands: &mut CommandReader<R>,
+) -> Result<(usize, Vec<Error>), Error> {
+    let mut count = 0;
+
+    let errors = commands.try_fold(Vec::new(), |mut errors, command| {
+        let (location, command) = match command {
+            Ok(command) => command,
+            Err(e @ Error::Parse(_)) if mode == Mode::Lenient => {
+                errors.push(e);
+                return Ok(errors);
+            }
+            Err(e) => return Err(e),
+        };
+
+        match lock(engine).run_command(command) {
+            Ok(_) => count += 1,
+            Err(e) if mode == Mode::Lenient => errors.push(RuntimeError(location, e).into()),
+            Err(e) => return Err(RuntimeError(location, e).into()),
+        }
+
+        Ok(errors)
+    })?;
+
+    Ok((count, errors))
+}
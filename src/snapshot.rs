@@ -0,0 +1,317 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+};
+
+use crate::{
+    common::*,
+    engine::{ClientData, EngineConfig, PaymentsEngine, TransactionData},
+    error::*,
+    output::AccountOrder,
+    parse::split_record,
+    store::{AccountStore, TransactionStore},
+    transaction::{TransactionKind, TransactionStatus},
+};
+
+// the first line of every snapshot, to be bumped whenever the format changes
+const VERSION: &str = "# payments-engine snapshot v2";
+
+// the line after the version, counting the commands journaled into the snapshot, see `PaymentsEngine::open_journal`
+const JOURNALED: &str = "journaled";
+
+const ACCOUNTS_SECTION: &str = "[accounts]";
+const ACCOUNTS_HEADER: &str = "client,available,held,locked";
+
+const TRANSACTIONS_SECTION: &str = "[transactions]";
+const TRANSACTIONS_HEADER: &str = "tx,client,type,amount,status";
+
+impl<A: AccountStore, T: TransactionStore> PaymentsEngine<A, T> {
+    /// Writes the full state of the engine, every account and every transaction, so it can be restored with `PaymentsEngine::read_snapshot`.
+    ///
+    /// The snapshot is a text file with a version line and a line counting the commands ever journaled into the state, followed by an `[accounts]` and a `[transactions]` section, each a CSV table with its own header.
+    /// Accounts are written in the order they were created, and transactions in the order the store iterates over them, one at a time, so a store larger than memory can still be saved.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from writing.
+    pub fn write_snapshot<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+        writeln!(writer, "{VERSION}")?;
+        writeln!(writer, "{JOURNALED},{}", self.journaled)?;
+
+        writeln!(writer, "{ACCOUNTS_SECTION}\n{ACCOUNTS_HEADER}")?;
+        for (id, data) in self.accounts_in_order(AccountOrder::Insertion)? {
+            writeln!(
+                writer,
+                "{id},{},{},{}",
+                data.available(),
+                data.held(),
+                data.locked()
+            )?;
+        }
+
+        writeln!(writer, "{TRANSACTIONS_SECTION}\n{TRANSACTIONS_HEADER}")?;
+        for transaction in self.transactions() {
+            let (tx, data) = transaction?;
+            writeln!(
+                writer,
+                "{tx},{},{},{},{}",
+                data.client(),
+                data.kind(),
+                data.amount(),
+                data.status()
+            )?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Writes a snapshot to the file at `path`, see `PaymentsEngine::write_snapshot`.
+    ///
+    /// The snapshot is first written to `path` with a `.tmp` suffix and synced to disk, then renamed over `path`, so a crash never leaves a partially written snapshot behind.
+    /// On Unix, the directory is synced after the rename as well, so the rename itself survives a crash.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from writing, syncing, or renaming the file.
+    pub fn save_snapshot(&self, path: &str) -> Result<(), Error> {
+        let temp_path = format!("{path}.tmp");
+
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        self.write_snapshot(&mut writer)?;
+
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        std::fs::rename(&temp_path, path)?;
+
+        // the rename is only durable once the directory holding it is synced, which only Unix allows opening
+        #[cfg(unix)]
+        {
+            let directory = match Path::new(path).parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            File::open(directory)?.sync_all()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl PaymentsEngine {
+    /// Restores an engine from a snapshot written by `PaymentsEngine::write_snapshot`, to keep running commands with `config`.
+    ///
+    /// # Errors
+    ///
+    /// Will return a `SnapshotError` if the snapshot cannot be read, is not in the expected format, has an unsupported version, or repeats a client or transaction id.
+    pub fn read_snapshot<R: BufRead>(reader: R, config: EngineConfig) -> Result<Self, Error> {
+        SnapshotReader::new(reader, None).read(config)
+    }
+
+    /// Restores an engine from the snapshot file at `path`, see `PaymentsEngine::read_snapshot`.
+    ///
+    /// # Errors
+    ///
+    /// Will return any error from `PaymentsEngine::read_snapshot`, naming the file in its location, or a `SnapshotError` if the file cannot be opened.
+    pub fn load_snapshot(path: &str, config: EngineConfig) -> Result<Self, Error> {
+        let file = File::open(path).map_err(|e| {
+            let location = Location {
+                file: Some(path.into()),
+                line: 0,
+                offset: 0,
+            };
+
+            SnapshotError(location, format!("could not open the snapshot: {e}"))
+        })?;
+
+        SnapshotReader::new(BufReader::new(file), Some(path.into())).read(config)
+    }
+}
+
+// reads a snapshot line by line, keeping track of the location for errors
+struct SnapshotReader<R: BufRead> {
+    reader: R,
+    file: Option<Arc<str>>,
+    line: usize,
+    offset: u64,
+    // offset of the start of the current line
+    start: u64,
+    buf: String,
+}
+
+impl<R: BufRead> SnapshotReader<R> {
+    fn new(reader: R, file: Option<Arc<str>>) -> Self {
+        Self {
+            reader,
+            file,
+            line: 0,
+            offset: 0,
+            start: 0,
+            buf: String::new(),
+        }
+    }
+
+    fn error(&self, message: String) -> Error {
+        let location = Location {
+            file: self.file.clone(),
+            line: self.line,
+            offset: self.start,
+        };
+
+        SnapshotError(location, message).into()
+    }
+
+    // reads the next line without its line ending, or `None` at the end of the stream
+    fn next_line(&mut self) -> Result<Option<&str>, Error> {
+        self.buf.clear();
+
+        let read = self.reader.read_line(&mut self.buf);
+
+        // a line that cannot be read is reported where it starts
+        self.start = self.offset;
+
+        let read = match read {
+            Ok(0) => return Ok(None),
+            Ok(read) => read,
+            Err(e) => {
+                self.line += 1;
+                return Err(self.error(format!("could not read the snapshot: {e}")));
+            }
+        };
+
+        self.line += 1;
+        self.offset += read as u64;
+
+        Ok(Some(self.buf.trim_end_matches(['\n', '\r'])))
+    }
+
+    // reads a line that must be exactly `expected`
+    fn expect_line(&mut self, expected: &str) -> Result<(), Error> {
+        match self.next_line()? {
+            Some(line) if line == expected => Ok(()),
+            Some(line) => {
+                let message = format!("expected {expected}, found {line}");
+                Err(self.error(message))
+            }
+            None => Err(self.error(format!("expected {expected}, found the end of the file"))),
+        }
+    }
+
+    // parses a single field, naming it in the error
+    fn field<T: FromStr>(&self, fields: &[String], i: usize, name: &str) -> Result<T, Error>
+    where
+        T::Err: std::fmt::Display,
+    {
+        fields[i]
+            .parse()
+            .map_err(|e| self.error(format!("could not parse {name} {}: {e}", fields[i])))
+    }
+
+    // splits the current line into exactly `count` fields
+    fn fields(&self, count: usize) -> Result<Vec<String>, Error> {
+        let line = self.buf.trim_end_matches(['\n', '\r']);
+
+        let fields = split_record(line)
+            .map_err(|e| self.error(e.to_string()))?
+            .into_iter()
+            .map(|x| x.into_owned())
+            .collect::<Vec<_>>();
+
+        if fields.len() != count {
+            return Err(self.error(format!("expected {count} fields, found {}", fields.len())));
+        }
+
+        Ok(fields)
+    }
+
+    fn read(mut self, config: EngineConfig) -> Result<PaymentsEngine, Error> {
+        if self.next_line()? != Some(VERSION) {
+            return Err(
+                self.error("not a snapshot, or a snapshot of an unsupported version".to_string())
+            );
+        }
+
+        let journaled = match self.next_line()? {
+            Some(line) if line.starts_with(&format!("{JOURNALED},")) => {
+                self.field::<u64>(&self.fields(2)?, 1, "journaled count")?
+            }
+            Some(line) => {
+                let message = format!("expected {JOURNALED},<count>, found {line}");
+                return Err(self.error(message));
+            }
+            None => {
+                return Err(self.error(format!(
+                    "expected {JOURNALED},<count>, found the end of the file"
+                )))
+            }
+        };
+
+        self.expect_line(ACCOUNTS_SECTION)?;
+        self.expect_line(ACCOUNTS_HEADER)?;
+
+        let mut accounts = Vec::new();
+        let mut clients = HashSet::new();
+
+        loop {
+            match self.next_line()? {
+                Some(TRANSACTIONS_SECTION) => break,
+                Some(_) => {}
+                None => {
+                    return Err(self.error(format!(
+                        "expected {TRANSACTIONS_SECTION}, found the end of the file"
+                    )))
+                }
+            }
+
+            let fields = self.fields(4)?;
+            let id = self.field::<ClientId>(&fields, 0, "client id")?;
+            let data = ClientData::new(
+                self.field(&fields, 1, "available funds")?,
+                self.field(&fields, 2, "held funds")?,
+                self.field(&fields, 3, "locked")?,
+            );
+
+            if !clients.insert(id) {
+                return Err(self.error(format!("client {id} appears more than once")));
+            }
+
+            accounts.push((id, data));
+        }
+
+        self.expect_line(TRANSACTIONS_HEADER)?;
+
+        let mut transactions = HashMap::new();
+
+        while self.next_line()?.is_some() {
+            let fields = self.fields(5)?;
+            let tx = self.field::<TransactionId>(&fields, 0, "transaction id")?;
+            let data = TransactionData::new(
+                self.field(&fields, 1, "client id")?,
+                self.field::<TransactionKind>(&fields, 2, "type")?,
+                self.field(&fields, 3, "amount")?,
+                self.field::<TransactionStatus>(&fields, 4, "status")?,
+            );
+
+            match transactions.entry(tx) {
+                Entry::Occupied(_) => {
+                    return Err(self.error(format!("transaction {tx} appears more than once")))
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(data);
+                }
+            }
+        }
+
+        let mut engine = PaymentsEngine::from_parts(config, accounts, transactions);
+        engine.journaled = journaled;
+
+        Ok(engine)
+    }
+}
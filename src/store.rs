@@ -0,0 +1,460 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    common::*,
+    engine::{ClientData, TransactionData},
+    transaction::{TransactionKind, TransactionStatus},
+};
+
+/// Where a `PaymentsEngine` keeps the accounts of its clients.
+///
+/// A `HashMap` is the default store, while `DiskAccountStore` keeps the accounts in a file.
+pub trait AccountStore {
+    /// Returns the account of `client`, if it has one.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from reading the store.
+    fn get(&self, client: ClientId) -> io::Result<Option<ClientData>>;
+
+    /// Inserts or replaces the account of `client`, returning the account it replaced, if any.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from writing the store.
+    fn insert(&mut self, client: ClientId, data: ClientData) -> io::Result<Option<ClientData>>;
+}
+
+/// Where a `PaymentsEngine` keeps every deposit and withdrawal, which is by far the largest part of its state.
+///
+/// A `HashMap` is the default store, while `DiskTransactionStore` keeps the transactions in a file.
+pub trait TransactionStore {
+    /// Returns the transaction with id `tx`, if there is one.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from reading the store.
+    fn get(&self, tx: TransactionId) -> io::Result<Option<TransactionData>>;
+
+    /// Inserts or replaces the transaction with id `tx`, returning the transaction it replaced, if any.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from writing the store.
+    fn insert(
+        &mut self,
+        tx: TransactionId,
+        data: TransactionData,
+    ) -> io::Result<Option<TransactionData>>;
+
+    /// Iterates over every transaction, in no particular order.
+    fn iter(&self) -> Box<dyn Iterator<Item = io::Result<(TransactionId, TransactionData)>> + '_>;
+}
+
+impl AccountStore for HashMap<ClientId, ClientData> {
+    fn get(&self, client: ClientId) -> io::Result<Option<ClientData>> {
+        Ok(self.get(&client).copied())
+    }
+
+    fn insert(&mut self, client: ClientId, data: ClientData) -> io::Result<Option<ClientData>> {
+        Ok(self.insert(client, data))
+    }
+}
+
+impl TransactionStore for HashMap<TransactionId, TransactionData> {
+    fn get(&self, tx: TransactionId) -> io::Result<Option<TransactionData>> {
+        Ok(self.get(&tx).copied())
+    }
+
+    fn insert(
+        &mut self,
+        tx: TransactionId,
+        data: TransactionData,
+    ) -> io::Result<Option<TransactionData>> {
+        Ok(self.insert(tx, data))
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = io::Result<(TransactionId, TransactionData)>> + '_> {
+        Box::new(HashMap::iter(self).map(|(tx, data)| Ok((*tx, *data))))
+    }
+}
+
+/// An `AccountStore` keeping the accounts in a hash table on disk, see `DiskTransactionStore`.
+#[derive(Debug)]
+pub struct DiskAccountStore(DiskTable<ClientId, ClientData>);
+
+impl DiskAccountStore {
+    /// Creates an empty store in a new file at `path`, replacing any file already there.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from creating the file.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        DiskTable::create(path.as_ref()).map(Self)
+    }
+}
+
+impl AccountStore for DiskAccountStore {
+    fn get(&self, client: ClientId) -> io::Result<Option<ClientData>> {
+        self.0.get(client)
+    }
+
+    fn insert(&mut self, client: ClientId, data: ClientData) -> io::Result<Option<ClientData>> {
+        self.0.insert(client, data)
+    }
+}
+
+/// A `TransactionStore` keeping the transactions in a hash table on disk, so the transaction history is not limited by memory.
+///
+/// Every record has a fixed size, and the table is doubled once it is three quarters full, so a lookup reads a handful of records at most.
+/// Only the number of records is kept in memory.
+///
+/// The file only makes sense to the engine that wrote it, so it is removed once the store is dropped.
+/// To keep the state of the engine between runs, save a snapshot or use a journal.
+#[derive(Debug)]
+pub struct DiskTransactionStore(DiskTable<TransactionId, TransactionData>);
+
+impl DiskTransactionStore {
+    /// Creates an empty store in a new file at `path`, replacing any file already there.
+    ///
+    /// # Errors
+    ///
+    /// Will return any IO error from creating the file.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        DiskTable::create(path.as_ref()).map(Self)
+    }
+}
+
+impl TransactionStore for DiskTransactionStore {
+    fn get(&self, tx: TransactionId) -> io::Result<Option<TransactionData>> {
+        self.0.get(tx)
+    }
+
+    fn insert(
+        &mut self,
+        tx: TransactionId,
+        data: TransactionData,
+    ) -> io::Result<Option<TransactionData>> {
+        self.0.insert(tx, data)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = io::Result<(TransactionId, TransactionData)>> + '_> {
+        self.0.iter()
+    }
+}
+
+// a key or value of fixed size, as stored in a `DiskTable`
+trait Record: Sized {
+    const SIZE: usize;
+
+    fn encode(&self, buf: &mut [u8]);
+
+    fn decode(buf: &[u8]) -> io::Result<Self>;
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+impl Record for u32 {
+    const SIZE: usize = 4;
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.to_le_bytes());
+    }
+
+    fn decode(buf: &[u8]) -> io::Result<Self> {
+        Ok(Self::from_le_bytes(buf.try_into().expect("4 bytes")))
+    }
+}
+
+impl Record for u64 {
+    const SIZE: usize = 8;
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.to_le_bytes());
+    }
+
+    fn decode(buf: &[u8]) -> io::Result<Self> {
+        Ok(Self::from_le_bytes(buf.try_into().expect("8 bytes")))
+    }
+}
+
+impl Record for Amount {
+    const SIZE: usize = 8;
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.raw().to_le_bytes());
+    }
+
+    fn decode(buf: &[u8]) -> io::Result<Self> {
+        Ok(Amount::from_raw(i64::from_le_bytes(
+            buf.try_into().expect("8 bytes"),
+        )))
+    }
+}
+
+// available, held, then whether the account is locked
+impl Record for ClientData {
+    const SIZE: usize = 17;
+
+    fn encode(&self, buf: &mut [u8]) {
+        self.available().encode(&mut buf[0..8]);
+        self.held().encode(&mut buf[8..16]);
+        buf[16] = self.locked().into();
+    }
+
+    fn decode(buf: &[u8]) -> io::Result<Self> {
+        let locked = match buf[16] {
+            0 => false,
+            1 => true,
+            _ => return Err(invalid_data("invalid locked flag")),
+        };
+
+        Ok(ClientData::new(
+            Amount::decode(&buf[0..8])?,
+            Amount::decode(&buf[8..16])?,
+            locked,
+        ))
+    }
+}
+
+// client, kind, amount, then status
+impl Record for TransactionData {
+    const SIZE: usize = 14;
+
+    fn encode(&self, buf: &mut [u8]) {
+        self.client().encode(&mut buf[0..4]);
+        buf[4] = match self.kind() {
+            TransactionKind::Deposit => 0,
+            TransactionKind::Withdrawal => 1,
+        };
+        self.amount().encode(&mut buf[5..13]);
+        buf[13] = match self.status() {
+            TransactionStatus::Ok => 0,
+            TransactionStatus::Disputed => 1,
+            TransactionStatus::Resolved => 2,
+            TransactionStatus::Chargeback => 3,
+            TransactionStatus::Declined => 4,
+        };
+    }
+
+    fn decode(buf: &[u8]) -> io::Result<Self> {
+        let kind = match buf[4] {
+            0 => TransactionKind::Deposit,
+            1 => TransactionKind::Withdrawal,
+            _ => return Err(invalid_data("invalid transaction kind")),
+        };
+        let status = match buf[13] {
+            0 => TransactionStatus::Ok,
+            1 => TransactionStatus::Disputed,
+            2 => TransactionStatus::Resolved,
+            3 => TransactionStatus::Chargeback,
+            4 => TransactionStatus::Declined,
+            _ => return Err(invalid_data("invalid transaction status")),
+        };
+
+        Ok(TransactionData::new(
+            ClientId::decode(&buf[0..4])?,
+            kind,
+            Amount::decode(&buf[5..13])?,
+            status,
+        ))
+    }
+}
+
+// the number of slots a new table starts with, always a power of two
+const INITIAL_CAPACITY: u64 = 1024;
+
+// an array of fixed-size slots in a file, each a byte telling whether it is used, followed by the key and the value
+//
+// unused slots are all zeros, so a new array is a sparse file of zeros
+#[derive(Debug)]
+struct Slots<K, V> {
+    file: File,
+    // always a power of two
+    capacity: u64,
+    _records: PhantomData<(K, V)>,
+}
+
+impl<K: Record + Copy + Eq + Into<u64>, V: Record> Slots<K, V> {
+    const SLOT_SIZE: usize = 1 + K::SIZE + V::SIZE;
+
+    fn create(path: &Path, capacity: u64) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(capacity * Self::SLOT_SIZE as u64)?;
+
+        Ok(Self {
+            file,
+            capacity,
+            _records: PhantomData,
+        })
+    }
+
+    // the slot a key is looked for first, by Fibonacci hashing so that sequential ids are spread out
+    fn home(&self, key: K) -> u64 {
+        let hash = key.into().wrapping_mul(0x9E37_79B9_7F4A_7C15);
+
+        hash >> (64 - self.capacity.trailing_zeros())
+    }
+
+    fn seek(&self, slot: u64) -> io::Result<&File> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(slot * Self::SLOT_SIZE as u64))?;
+
+        Ok(file)
+    }
+
+    fn write(&self, slot: u64, key: K, value: &V) -> io::Result<()> {
+        let mut buf = vec![0; Self::SLOT_SIZE];
+        buf[0] = 1;
+        key.encode(&mut buf[1..1 + K::SIZE]);
+        value.encode(&mut buf[1 + K::SIZE..]);
+
+        self.seek(slot)?.write_all(&buf)
+    }
+
+    // finds the slot holding `key`, or the empty slot it would go in, along with its value if it is there
+    fn find(&self, key: K) -> io::Result<(u64, Option<V>)> {
+        let mut buf = vec![0; Self::SLOT_SIZE];
+        let mut slot = self.home(key);
+
+        loop {
+            self.seek(slot)?.read_exact(&mut buf)?;
+
+            match buf[0] {
+                0 => return Ok((slot, None)),
+                1 if K::decode(&buf[1..1 + K::SIZE])? == key => {
+                    return Ok((slot, Some(V::decode(&buf[1 + K::SIZE..])?)))
+                }
+                1 => {}
+                _ => return Err(invalid_data("invalid slot")),
+            }
+
+            // the array is never full, so an empty slot is always found
+            slot = (slot + 1) & (self.capacity - 1);
+        }
+    }
+}
+
+// a hash table in a file, with linear probing
+#[derive(Debug)]
+struct DiskTable<K, V> {
+    path: PathBuf,
+    slots: Slots<K, V>,
+    len: u64,
+}
+
+impl<K: Record + Copy + Eq + Into<u64>, V: Record> DiskTable<K, V> {
+    fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            slots: Slots::create(path, INITIAL_CAPACITY)?,
+            len: 0,
+        })
+    }
+
+    fn get(&self, key: K) -> io::Result<Option<V>> {
+        self.slots.find(key).map(|(_, value)| value)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> io::Result<Option<V>> {
+        let (mut slot, old) = self.slots.find(key)?;
+        if old.is_some() {
+            self.slots.write(slot, key, &value)?;
+            return Ok(old);
+        }
+
+        // keep the table at most three quarters full, so probes stay short
+        if (self.len + 1) * 4 > self.slots.capacity * 3 {
+            self.grow()?;
+            slot = self.slots.find(key)?.0;
+        }
+
+        self.slots.write(slot, key, &value)?;
+        self.len += 1;
+
+        Ok(None)
+    }
+
+    // doubles the capacity, moving every record to a new file that then replaces the old one
+    fn grow(&mut self) -> io::Result<()> {
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".grow");
+        let temp_path = PathBuf::from(temp_path);
+
+        // a rebuild that fails part way leaves the table as it was, and its new file is removed rather than left behind
+        let grown = self.rebuild(&temp_path).and_then(|grown| {
+            std::fs::rename(&temp_path, &self.path)?;
+            Ok(grown)
+        });
+
+        match grown {
+            Ok(grown) => {
+                self.slots = grown;
+                Ok(())
+            }
+            Err(e) => {
+                let _ = std::fs::remove_file(&temp_path);
+                Err(e)
+            }
+        }
+    }
+
+    // copies every record into new slots of twice the capacity, in a file at `path`
+    fn rebuild(&self, path: &Path) -> io::Result<Slots<K, V>> {
+        let grown = Slots::create(path, self.slots.capacity * 2)?;
+        for record in self.iter() {
+            let (key, value) = record?;
+            let (slot, _) = grown.find(key)?;
+            grown.write(slot, key, &value)?;
+        }
+
+        Ok(grown)
+    }
+
+    // reads every used slot in order, through a handle of its own so lookups can still be made meanwhile
+    fn iter(&self) -> Box<dyn Iterator<Item = io::Result<(K, V)>> + '_> {
+        let mut reader = match File::open(&self.path) {
+            Ok(file) => BufReader::new(file),
+            Err(e) => return Box::new(std::iter::once(Err(e))),
+        };
+        let mut buf = vec![0; Slots::<K, V>::SLOT_SIZE];
+        let mut remaining = self.slots.capacity;
+
+        Box::new(std::iter::from_fn(move || loop {
+            if remaining == 0 {
+                return None;
+            }
+            remaining -= 1;
+
+            if let Err(e) = reader.read_exact(&mut buf) {
+                remaining = 0;
+                return Some(Err(e));
+            }
+
+            if buf[0] != 0 {
+                let record = K::decode(&buf[1..1 + K::SIZE])
+                    .and_then(|key| Ok((key, V::decode(&buf[1 + K::SIZE..])?)));
+                return Some(record);
+            }
+        }))
+    }
+}
+
+impl<K, V> Drop for DiskTable<K, V> {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    future::poll_fn,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+
+use futures_core::Stream;
+use tokio::io::AsyncBufRead;
+
+use crate::{
+    common::*,
+    engine::{CommandOutcome, PaymentsEngine},
+    error::*,
+    parse::{AmountLimits, ColumnMap, Records, MAX_RECORD},
+    store::{AccountStore, TransactionStore},
+};
+
+/// Reads payment commands from an async CSV stream, such as a socket, in the same format as `CommandReader`.
+///
+/// Lines are only read as the stream is polled, so the input is read no faster than the commands are taken.
+/// Like `CommandReader`, a record may take up at most 64 KiB.
+pub struct AsyncCommandReader<R> {
+    reader: R,
+    // the bytes of the line being read, which may arrive over several reads
+    line: Vec<u8>,
+    // whether the rest of an overlong line is being discarded
+    skipping: bool,
+    records: Records,
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncCommandReader<R> {
+    /// Creates a new `AsyncCommandReader`, reading and checking the CSV header.
+    ///
+    /// # Errors
+    ///
+    /// Will return any error from reading the header, see `CommandReader::new`.
+    pub async fn new(reader: R) -> Result<Self, Error> {
+        Self::open(reader, None, AmountLimits::default()).await
+    }
+
+    /// Creates a new `AsyncCommandReader` that rejects deposits and withdrawals outside of `limits`, reading and checking the CSV header.
+    ///
+    /// # Errors
+    ///
+    /// Will return any error from reading the header, see `CommandReader::new`.
+    pub async fn with_limits(reader: R, limits: AmountLimits) -> Result<Self, Error> {
+        Self::open(reader, None, limits).await
+    }
+
+    /// Creates a new `AsyncCommandReader` like `AsyncCommandReader::with_limits`, naming `file` in the location of every command and error.
+    ///
+    /// # Errors
+    ///
+    /// Will return any error from reading the header, see `CommandReader::new`.
+    pub async fn with_file(reader: R, file: &str, limits: AmountLimits) -> Result<Self, Error> {
+        Self::open(reader, Some(file.into()), limits).await
+    }
+
+    async fn open(reader: R, file: Option<Arc<str>>, limits: AmountLimits) -> Result<Self, Error> {
+        let mut command_reader = Self {
+            reader,
+            line: Vec::new(),
+            skipping: false,
+            records: Records::new(file, limits),
+        };
+
+        let location = poll_fn(|cx| command_reader.poll_record(cx)).await?;
+        command_reader.records.header(location)?;
+
+        Ok(command_reader)
+    }
+
+    /// Returns the column mapping read from the header.
+    pub fn columns(&self) -> &ColumnMap {
+        self.records.columns()
+    }
+
+    // reads lines until a record is complete, returning the location it starts at
+    fn poll_record(&mut self, cx: &mut Context<'_>) -> Poll<Result<Option<Location>, Error>> {
+        loop {
+            let buf = ready!(Pin::new(&mut self.reader).poll_fill_buf(cx))?;
+
+            // the last line may lack a line ending
+            if buf.is_empty() {
+                if !self.line.is_empty() {
+                    if let Some(location) = self.push_line()? {
+                        return Poll::Ready(Ok(Some(location)));
+                    }
+                }
+
+                return Poll::Ready(Ok(self.records.finish()));
+            }
+
+            let end = buf.iter().position(|b| *b == b'\n').map(|end| end + 1);
+
+            // the rest of an overlong line is discarded, so reading carries on from the next line
+            if self.skipping {
+                let read = end.unwrap_or(buf.len());
+
+                self.skipping = end.is_none();
+                self.records.skip(read);
+                Pin::new(&mut self.reader).consume(read);
+                continue;
+            }
+
+            // as in `CommandReader`, a line is read no further than one byte past `MAX_RECORD`
+            let read = end
+                .unwrap_or(buf.len())
+                .min(MAX_RECORD + 1 - self.line.len());
+
+            self.line.extend_from_slice(&buf[..read]);
+            Pin::new(&mut self.reader).consume(read);
+
+            let complete = self.line.ends_with(b"\n");
+
+            if complete || self.line.len() > MAX_RECORD {
+                self.skipping = !complete;
+
+                if let Some(location) = self.push_line()? {
+                    return Poll::Ready(Ok(Some(location)));
+                }
+            }
+        }
+    }
+
+    // hands the line read to the records
+    fn push_line(&mut self) -> Result<Option<Location>, Error> {
+        let location = self.records.push_line(&self.line);
+
+        self.line.clear();
+
+        location
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> Stream for AsyncCommandReader<R> {
+    type Item = Result<(Location, PaymentCommand), Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        Poll::Ready(match ready!(this.poll_record(cx)) {
+            Ok(Some(location)) => Some(this.records.command(location)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        })
+    }
+}
+
+/// The outcome of every command of a stream, see `PaymentsEngine::run_stream`.
+///
+/// Commands are run on the task polling the stream, so journaling and disk stores block it while they write.
+pub struct Outcomes<'a, S, A, T> {
+    engine: &'a mut PaymentsEngine<A, T>,
+    commands: S,
+}
+
+impl<S, A, T> Stream for Outcomes<'_, S, A, T>
+where
+    S: Stream<Item = Result<(Location, PaymentCommand), Error>> + Unpin,
+    A: AccountStore,
+    T: TransactionStore,
+{
+    type Item = Result<(Location, PaymentCommand, CommandOutcome), Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            let (location, command) = match ready!(Pin::new(&mut this.commands).poll_next(cx)) {
+                Some(Ok(command)) => command,
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => return Poll::Ready(None),
+            };
+
+            let outcome = match this.engine.run_journaled(&location, command) {
+                Ok(Some(Ok(outcome))) => Ok((location, command, outcome)),
+                Ok(Some(Err(e))) => Err(RuntimeError(location, e).into()),
+                // already run before the journal was reopened
+                Ok(None) => continue,
+                Err(e) => Err(e),
+            };
+
+            return Poll::Ready(Some(outcome));
+        }
+    }
+}
+
+impl<A: AccountStore, T: TransactionStore> PaymentsEngine<A, T> {
+    /// Runs payment commands from a stream, such as an `AsyncCommandReader`, returning a stream of their outcomes.
+    ///
+    /// A command is only taken from `commands` once its outcome is polled for, so a slow consumer of the outcomes holds back the producer of the commands.
+    /// Errors are yielded in place of the outcome, and the stream carries on after them, leaving it to the caller to stop at the first error or to skip it.
+    ///
+    /// If a journal is open, commands are appended to it as they are by `PaymentsEngine::run_commands_with`, and those it already holds are skipped.
+    /// Appending to the journal syncs it to disk, and a disk store reads and writes its files, both without yielding to the executor.
+    /// With either, poll the outcomes from a task that may block, such as one started with `tokio::task::spawn_blocking`, or on a runtime with threads to spare.
+    pub fn run_stream<S>(&mut self, commands: S) -> Outcomes<'_, S, A, T>
+    where
+        S: Stream<Item = Result<(Location, PaymentCommand), Error>> + Unpin,
+    {
+        Outcomes {
+            engine: self,
+            commands,
+        }
+    }
+}
@@ -1,19 +1,56 @@
 #[allow(unused_imports)]
-use crate::{common::*, engine::*, error::*, parse::*};
+use crate::{
+    common::*, engine::*, error::*, output::*, parse::*, report::*, store::*, transaction::*,
+};
+
+// creates empty disk stores in the temporary directory, unique to the test and process
+fn disk_stores(name: &str) -> Result<(DiskAccountStore, DiskTransactionStore), crate::Error> {
+    let path = |kind: &str| {
+        std::env::temp_dir().join(format!(
+            "payments-engine-{}-{name}.{kind}",
+            std::process::id()
+        ))
+    };
+
+    Ok((
+        DiskAccountStore::create(path("accounts"))?,
+        DiskTransactionStore::create(path("transactions"))?,
+    ))
+}
+
+// runs the commands of a file across several threads, returning the errors skipped in lenient mode
+fn run_parallel(
+    engine: &mut PaymentsEngine,
+    path: &str,
+    mode: Mode,
+) -> Result<Vec<crate::Error>, crate::Error> {
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let commands = CommandReader::with_file(file, path, engine.config().amount_limits)?;
+
+    engine.run_commands_parallel(
+        commands,
+        std::num::NonZeroUsize::new(4).unwrap(),
+        mode,
+        |_, _, _| Ok(()),
+    )
+}
 
 macro_rules! gen_test {
-    ($name:ident, should_err = $should_err:expr, $err_pat:pat = err) => {
+    (@run $name:ident, $config:expr, should_err = $should_err:expr, $err_pat:pat = err) => {
         mod $name {
             use super::*;
 
-            #[test]
-            pub fn test() -> Result<(), crate::Error> {
+            fn check<A, T, R>(mut engine: PaymentsEngine<A, T>, run: R) -> Result<(), crate::Error>
+            where
+                A: AccountStore,
+                T: TransactionStore,
+                R: FnOnce(&mut PaymentsEngine<A, T>, &str) -> Result<(), crate::Error>,
+            {
                 let name_str = stringify!($name);
                 let in_filename = format!("test_cases/{}_input.csv", name_str);
                 let out_filename = format!("test_cases/{}_output.csv", name_str);
 
-                let mut engine = PaymentsEngine::new();
-                let res = engine.run_from_file(&in_filename);
+                let res = run(&mut engine, &in_filename);
 
                 if $should_err {
                     assert!(res.is_err());
@@ -29,28 +66,62 @@ macro_rules! gen_test {
                 let expected_out = std::fs::read_to_string(out_filename)
                     .map_err(|e| Error::Other(format!("IO Error: {}", e)))?;
 
-                let engine_out = engine.to_csv_string_sorted();
+                let engine_out = engine.to_csv_string_sorted()?;
 
                 assert_eq!(expected_out, engine_out);
 
                 Ok(())
             }
+
+            #[test]
+            pub fn test() -> Result<(), crate::Error> {
+                check(PaymentsEngine::with_config($config), |engine, path| {
+                    engine.run_from_file(path)
+                })
+            }
+
+            // the same case, with the accounts and transactions kept on disk
+            #[test]
+            pub fn disk() -> Result<(), crate::Error> {
+                let (accounts, transactions) = disk_stores(stringify!($name))?;
+
+                check(
+                    PaymentsEngine::with_stores($config, accounts, transactions),
+                    |engine, path| engine.run_from_file(path),
+                )
+            }
+
+            // the same case, with the clients split across several threads
+            #[test]
+            pub fn parallel() -> Result<(), crate::Error> {
+                check(PaymentsEngine::with_config($config), |engine, path| {
+                    run_parallel(engine, path, Mode::Strict).map(|_| ())
+                })
+            }
         }
     };
 
+    ($name:ident ($config:expr), $err_pat:pat = err) => {
+        gen_test!(@run $name, $config, should_err = true, $err_pat = err);
+    };
+
+    ($name:ident ($config:expr)) => {
+        gen_test!(@run $name, $config, should_err = false, _ = err);
+    };
+
     ($name:ident, $err_pat:pat = err) => {
-        gen_test!($name, should_err = true, $err_pat = err);
+        gen_test!(@run $name, EngineConfig::default(), should_err = true, $err_pat = err);
     };
 
     ($name:ident) => {
-        gen_test!($name, should_err = false, _ = err);
+        gen_test!(@run $name, EngineConfig::default(), should_err = false, _ = err);
     };
 }
 
 macro_rules! gen_tests {
-    {$($name:ident $(, $err_pat:pat = err)?);*$(;)?} => {
+    {$($name:ident $(($config:expr))? $(, $err_pat:pat = err)?);*$(;)?} => {
         $(
-            gen_test! { $name $(, $err_pat = err)? }
+            gen_test! { $name $(($config))? $(, $err_pat = err)? }
         )*
     };
 }
@@ -61,6 +132,1752 @@ gen_tests! {
     t2;
     t3;
     t4;
-    t5, Error::Parse(ParseError(0, PaymentCommandParseError::MissingHeader(_))) = err;
-    t6, Error::Runtime(RuntimeError(7, EngineError::ClientIdMismatch(2, 1))) = err;
+    t5, Error::Parse(ParseError(Location { line: 1, .. }, PaymentCommandParseError::MissingHeader(_))) = err;
+    t6, Error::Runtime(RuntimeError(Location { line: 7, .. }, EngineError::ClientIdMismatch(2, 1))) = err;
+    t7;
+    t8, Error::Parse(ParseError(Location { line: 3, .. }, PaymentCommandParseError::ParseError(_))) = err;
+    t9;
+    t10, Error::Parse(ParseError(Location { line: 3, .. }, PaymentCommandParseError::IdOutOfRange(_))) = err;
+    t11, Error::Runtime(RuntimeError(Location { line: 4, .. }, EngineError::DuplicateTransaction(1))) = err;
+    t12, Error::Runtime(RuntimeError(Location { line: 5, .. }, EngineError::DuplicateTransaction(2))) = err;
+    t13, Error::Runtime(RuntimeError(Location { line: 6, .. }, EngineError::AccountLocked(1))) = err;
+    t14, Error::Runtime(RuntimeError(Location { line: 6, .. }, EngineError::AccountLocked(1))) = err;
+    t15, Error::Runtime(RuntimeError(Location { line: 6, .. }, EngineError::AccountLocked(1))) = err;
+    t16;
+    t17;
+    t18(EngineConfig {
+        invalid_transitions: TransitionPolicy::Reject,
+        ..Default::default()
+    }), Error::Runtime(RuntimeError(
+        Location { line: 5, .. },
+        EngineError::InvalidTransition(TransactionStatus::Resolved, TransactionStatus::Disputed)
+    )) = err;
+    t19(EngineConfig {
+        redisputes: RedisputePolicy::Allow,
+        ..Default::default()
+    });
+    t20(EngineConfig {
+        invalid_transitions: TransitionPolicy::Reject,
+        ..Default::default()
+    }), Error::Runtime(RuntimeError(
+        Location { line: 6, .. },
+        EngineError::InvalidTransition(TransactionStatus::Chargeback, TransactionStatus::Resolved)
+    )) = err;
+    t21, Error::Runtime(RuntimeError(Location { line: 4, .. }, EngineError::DisputedWithdrawal(2))) = err;
+    t22(EngineConfig {
+        withdrawal_disputes: WithdrawalDisputePolicy::ReverseFlow,
+        ..Default::default()
+    });
+    t23(EngineConfig {
+        withdrawal_disputes: WithdrawalDisputePolicy::ReverseFlow,
+        ..Default::default()
+    });
+    t24;
+    t25;
+    t26, Error::Parse(ParseError(Location { line: 1, .. }, PaymentCommandParseError::MissingHeader(_))) = err;
+    t27, Error::Parse(ParseError(Location { line: 3, .. }, PaymentCommandParseError::InvalidAmount(_))) = err;
+    t28, Error::Parse(ParseError(Location { line: 3, .. }, PaymentCommandParseError::InvalidAmount(_))) = err;
+    t29, Error::Parse(ParseError(Location { line: 3, .. }, PaymentCommandParseError::InvalidAmount(_))) = err;
+    t30(EngineConfig {
+        amount_limits: AmountLimits {
+            min: Some(Amount::from_raw(10_000)),
+            max: Some(Amount::from_raw(1_000_000)),
+        },
+        ..Default::default()
+    }), Error::Parse(ParseError(Location { line: 4, .. }, PaymentCommandParseError::InvalidAmount(_))) = err;
+    t31(EngineConfig {
+        amount_limits: AmountLimits {
+            min: Some(Amount::from_raw(10_000)),
+            max: Some(Amount::from_raw(1_000_000)),
+        },
+        ..Default::default()
+    });
+    // the repeated dispute of t4, which is only an error when asked for
+    t32(EngineConfig {
+        invalid_transitions: TransitionPolicy::Reject,
+        ..Default::default()
+    }), Error::Runtime(RuntimeError(
+        Location { line: 8, .. },
+        EngineError::InvalidTransition(TransactionStatus::Disputed, TransactionStatus::Disputed)
+    )) = err;
+    // disputes of declined withdrawals, which must not leave the new client they name with an account
+    t33(EngineConfig {
+        withdrawal_disputes: WithdrawalDisputePolicy::ReverseFlow,
+        ..Default::default()
+    });
+}
+
+#[test]
+fn amount_parse() {
+    assert_eq!("1".parse(), Ok(Amount::from_raw(10_000)));
+    assert_eq!("1.5".parse(), Ok(Amount::from_raw(15_000)));
+    assert_eq!("-0.0001".parse(), Ok(Amount::from_raw(-1)));
+    assert_eq!(".25".parse(), Ok(Amount::from_raw(2_500)));
+    assert_eq!("2.0000000".parse(), Ok(Amount::from_raw(20_000)));
+
+    assert_eq!("".parse::<Amount>(), Err(AmountParseError::Empty));
+    assert_eq!(
+        "1.2.3".parse::<Amount>(),
+        Err(AmountParseError::InvalidDigit)
+    );
+    assert_eq!("NaN".parse::<Amount>(), Err(AmountParseError::InvalidDigit));
+    assert_eq!("1e3".parse::<Amount>(), Err(AmountParseError::InvalidDigit));
+    assert_eq!(
+        "0.00001".parse::<Amount>(),
+        Err(AmountParseError::TooPrecise)
+    );
+    assert_eq!(
+        "1000000000000000".parse::<Amount>(),
+        Err(AmountParseError::Overflow)
+    );
+}
+
+#[test]
+fn amount_display() {
+    assert_eq!(Amount::from_raw(0).to_string(), "0.0000");
+    assert_eq!(Amount::from_raw(15_000).to_string(), "1.5000");
+    assert_eq!(Amount::from_raw(-1).to_string(), "-0.0001");
+    assert_eq!(
+        Amount::from_raw(i64::MIN).to_string(),
+        "-922337203685477.5808"
+    );
+}
+
+#[test]
+fn invalid_amounts() -> Result<(), crate::Error> {
+    // commands built by hand are checked as they are run, so a negative deposit cannot drain an account
+    let mut engine = PaymentsEngine::with_config(EngineConfig {
+        amount_limits: AmountLimits {
+            min: None,
+            max: Some(Amount::from_raw(1_000_000)),
+        },
+        ..Default::default()
+    });
+    let deposit = |tx, raw| PaymentCommand::Deposit {
+        client: 1,
+        tx,
+        amount: Amount::from_raw(raw),
+    };
+
+    assert_eq!(
+        engine.run_command(deposit(1, 10_000)),
+        Ok(CommandOutcome::Applied)
+    );
+    for (tx, raw) in [(2, -50_000), (3, 0), (4, 1_000_001)] {
+        assert!(matches!(
+            engine.run_command(deposit(tx, raw)),
+            Err(EngineError::InvalidAmount(
+                PaymentCommandParseError::InvalidAmount(_)
+            ))
+        ));
+    }
+    assert!(matches!(
+        engine.run_command(PaymentCommand::Withdrawal {
+            client: 1,
+            tx: 5,
+            amount: Amount::from_raw(-50_000),
+        }),
+        Err(EngineError::InvalidAmount(_))
+    ));
+
+    // a refused amount never takes up its transaction id
+    assert_eq!(engine.get_transaction(2)?.map(|x| x.amount()), None);
+    assert_eq!(
+        engine.to_csv_string_sorted()?,
+        "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false"
+    );
+
+    // numbers that are not decimals are invalid amounts, while anything else is unreadable
+    for amount in ["NaN", "inf", "-inf", "1e308", "1000000000000000"] {
+        assert!(matches!(
+            PaymentCommand::from_csv_line(&format!("deposit,1,1,{amount}")),
+            Err(PaymentCommandParseError::InvalidAmount(_))
+        ));
+    }
+    assert!(matches!(
+        PaymentCommand::from_csv_line("deposit,1,1,five"),
+        Err(PaymentCommandParseError::ParseError(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn run_from_reader() -> Result<(), crate::Error> {
+    let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\r\nwithdrawal, 1, 2, 0.25\n";
+
+    let mut engine = PaymentsEngine::new();
+    engine.run_from_reader(input.as_bytes())?;
+
+    assert_eq!(
+        engine.to_csv_string_sorted()?,
+        "client,available,held,total,locked\n1,0.7500,0.0000,0.7500,false"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn command_reader_line_numbers() {
+    let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\ndeposit, 1, 2\n";
+
+    let mut reader = CommandReader::new(input.as_bytes()).unwrap();
+
+    assert!(matches!(
+        reader.next(),
+        Some(Ok((Location { line: 2, .. }, _)))
+    ));
+    assert!(matches!(
+        reader.next(),
+        Some(Err(Error::Parse(ParseError(
+            Location { line: 3, .. },
+            PaymentCommandParseError::MissingData(_)
+        ))))
+    ));
+    assert!(reader.next().is_none());
+}
+
+#[test]
+fn millions_of_transactions() -> Result<(), EngineError> {
+    const COUNT: TransactionId = 2_000_000;
+    // start past the range of a u32 to make sure the full id width is used
+    const FIRST: TransactionId = u32::MAX as TransactionId;
+
+    let mut engine = PaymentsEngine::with_capacity(1, COUNT as usize);
+
+    for tx in FIRST..FIRST + COUNT {
+        engine.run_command(PaymentCommand::Deposit {
+            client: 1,
+            tx,
+            amount: Amount::from_raw(1),
+        })?;
+    }
+
+    engine.run_command(PaymentCommand::Dispute {
+        client: 1,
+        tx: FIRST,
+    })?;
+    engine.run_command(PaymentCommand::Dispute {
+        client: 1,
+        tx: FIRST + COUNT - 1,
+    })?;
+
+    assert_eq!(
+        engine.to_csv_string_sorted().unwrap(),
+        "client,available,held,total,locked\n1,199.9998,0.0002,200.0000,false"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn duplicate_transaction_keeps_original() {
+    let mut engine = PaymentsEngine::new();
+
+    let deposit = |tx, amount| PaymentCommand::Deposit {
+        client: 1,
+        tx,
+        amount: Amount::from_raw(amount),
+    };
+
+    assert!(engine.run_command(deposit(1, 10_000)).is_ok());
+    assert!(engine.run_command(deposit(2, 20_000)).is_ok());
+    assert!(matches!(
+        engine.run_command(deposit(1, 50_000)),
+        Err(EngineError::DuplicateTransaction(1))
+    ));
+    assert!(engine
+        .run_command(PaymentCommand::Dispute { client: 1, tx: 1 })
+        .is_ok());
+
+    assert_eq!(
+        engine.to_csv_string_sorted().unwrap(),
+        "client,available,held,total,locked\n1,2.0000,1.0000,3.0000,false"
+    );
+}
+
+#[test]
+fn command_outcomes() {
+    let mut engine = PaymentsEngine::new();
+
+    let mut run = |command| engine.run_command(command);
+    let ignored = |reason| Ok(CommandOutcome::Ignored(reason));
+
+    let deposit = PaymentCommand::Deposit {
+        client: 1,
+        tx: 1,
+        amount: Amount::from_raw(10_000),
+    };
+    let withdrawal = PaymentCommand::Withdrawal {
+        client: 1,
+        tx: 2,
+        amount: Amount::from_raw(20_000),
+    };
+
+    assert_eq!(run(deposit), Ok(CommandOutcome::Applied));
+    assert_eq!(run(withdrawal), ignored(IgnoreReason::InsufficientFunds));
+    assert_eq!(
+        run(PaymentCommand::Dispute { client: 1, tx: 3 }),
+        ignored(IgnoreReason::UnknownTransaction)
+    );
+    assert_eq!(
+        run(PaymentCommand::Dispute { client: 1, tx: 1 }),
+        Ok(CommandOutcome::Applied)
+    );
+
+    // illegal transitions are ignored by default
+    assert_eq!(
+        run(PaymentCommand::Dispute { client: 1, tx: 1 }),
+        ignored(IgnoreReason::AlreadyDisputed)
+    );
+    assert_eq!(
+        run(PaymentCommand::Resolve { client: 1, tx: 1 }),
+        Ok(CommandOutcome::Applied)
+    );
+    assert_eq!(
+        run(PaymentCommand::Chargeback { client: 1, tx: 1 }),
+        ignored(IgnoreReason::NotDisputed)
+    );
+    assert_eq!(
+        run(PaymentCommand::Dispute { client: 1, tx: 1 }),
+        ignored(IgnoreReason::NotDisputable)
+    );
+}
+
+#[test]
+fn transitions() {
+    use TransactionEvent as E;
+    use TransactionStatus as S;
+
+    let deny = RedisputePolicy::Deny;
+    let allow = RedisputePolicy::Allow;
+
+    assert_eq!(S::Ok.transition(E::Dispute, deny), Ok(S::Disputed));
+    assert_eq!(S::Disputed.transition(E::Resolve, deny), Ok(S::Resolved));
+    assert_eq!(
+        S::Disputed.transition(E::Chargeback, deny),
+        Ok(S::Chargeback)
+    );
+    assert_eq!(S::Resolved.transition(E::Dispute, allow), Ok(S::Disputed));
+
+    let illegal = [
+        (S::Resolved, E::Dispute, deny),
+        (S::Ok, E::Resolve, allow),
+        (S::Ok, E::Chargeback, allow),
+        (S::Disputed, E::Dispute, allow),
+        (S::Resolved, E::Resolve, allow),
+        (S::Resolved, E::Chargeback, allow),
+        (S::Chargeback, E::Dispute, allow),
+        (S::Chargeback, E::Resolve, allow),
+        (S::Declined, E::Dispute, allow),
+    ];
+
+    for (from, event, redisputes) in illegal {
+        assert_eq!(
+            from.transition(event, redisputes),
+            Err(EngineError::InvalidTransition(from, event.target()))
+        );
+    }
+}
+
+#[test]
+fn rejection_log() -> Result<(), crate::Error> {
+    let input = std::fs::read_to_string("test_cases/t2_input.csv")?;
+    let commands =
+        CommandReader::with_file(input.as_bytes(), "t2, input.csv", AmountLimits::default())?;
+
+    let mut buf = Vec::new();
+    let mut rejections = RejectionWriter::new(&mut buf)?;
+
+    PaymentsEngine::new().run_commands_with(
+        commands,
+        Mode::Strict,
+        |location, command, outcome| match outcome {
+            CommandOutcome::Applied => Ok(()),
+            CommandOutcome::Ignored(reason) => rejections.write(location, command, reason),
+        },
+    )?;
+
+    assert_eq!(
+        String::from_utf8(buf).unwrap(),
+        "file,line,tx,client,reason\n\"t2, input.csv\",6,5,2,insufficient funds\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn lenient_mode() -> Result<(), crate::Error> {
+    let input = "type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 1, 2
+deposit, 2, 3, 2.0
+dispute, 2, 1
+transfer, 1, 4, 1.0
+withdrawal, 2, 5, 0.5
+";
+
+    let mut engine = PaymentsEngine::new();
+    let errors = engine.run_from_reader_with(input.as_bytes(), Mode::Lenient, |_, _, _| Ok(()))?;
+
+    assert!(matches!(
+        errors[..],
+        [
+            Error::Parse(ParseError(
+                Location { line: 3, .. },
+                PaymentCommandParseError::MissingData(_)
+            )),
+            Error::Runtime(RuntimeError(
+                Location { line: 5, .. },
+                EngineError::ClientIdMismatch(2, 1)
+            )),
+            Error::Parse(ParseError(
+                Location { line: 6, .. },
+                PaymentCommandParseError::NoSuchPaymentCommand(_)
+            )),
+        ]
+    ));
+
+    assert_eq!(
+        engine.to_csv_string_sorted()?,
+        "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n2,1.5000,0.0000,1.5000,false"
+    );
+
+    // the same input fails on the first error in strict mode
+    assert!(matches!(
+        PaymentsEngine::new().run_from_reader(input.as_bytes()),
+        Err(Error::Parse(ParseError(Location { line: 3, .. }, _)))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn accessors() -> Result<(), crate::Error> {
+    let mut engine = PaymentsEngine::new();
+    engine.run_from_file("test_cases/t1_input.csv")?;
+
+    let account = engine.get_account(1)?.unwrap();
+    assert_eq!(account.available(), Amount::from_raw(5_000));
+    assert_eq!(account.held(), Amount::from_raw(10_000));
+    assert_eq!(account.total(), Amount::from_raw(15_000));
+    assert!(!account.locked());
+    assert!(engine.get_account(3)?.is_none());
+
+    let mut clients = engine
+        .accounts()
+        .map(|x| x.map(|(id, _)| id))
+        .collect::<Result<Vec<_>, _>>()?;
+    clients.sort();
+    assert_eq!(clients, [1, 2]);
+
+    let transaction = engine.get_transaction(1)?.unwrap();
+    assert_eq!(transaction.client(), 1);
+    assert_eq!(transaction.kind(), TransactionKind::Deposit);
+    assert_eq!(transaction.amount(), Amount::from_raw(10_000));
+    assert_eq!(transaction.status(), TransactionStatus::Disputed);
+
+    // the withdrawal with insufficient funds is kept, but declined
+    assert_eq!(
+        engine.get_transaction(5)?.unwrap().status(),
+        TransactionStatus::Declined
+    );
+    assert!(engine.get_transaction(6)?.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn split_records() {
+    assert_eq!(split_record("a, b ,c").unwrap(), ["a", "b", "c"]);
+    assert_eq!(
+        split_record("dispute,1,1,").unwrap(),
+        ["dispute", "1", "1", ""]
+    );
+    assert_eq!(
+        split_record(" \"a,b\" , \"say \"\"hi\"\"\",\"line\nbreak\"").unwrap(),
+        ["a,b", "say \"hi\"", "line\nbreak"]
+    );
+    assert_eq!(split_record("").unwrap(), [""]);
+
+    assert!(matches!(
+        split_record(r#"deposit,"1"#),
+        Err(PaymentCommandParseError::ParseError(_))
+    ));
+    assert!(matches!(
+        split_record(r#"deposit,"1"2"#),
+        Err(PaymentCommandParseError::ParseError(_))
+    ));
+}
+
+#[test]
+fn crlf_bom_and_multiline_records() {
+    let input = "\u{feff}type,client,tx,amount\r\ndeposit,1,1,1.0\r\n\r\n\"deposit\",1,2,\"2\r\n\"\r\ndeposit,1,3\r\n";
+
+    let mut reader = CommandReader::new(input.as_bytes()).unwrap();
+
+    assert!(matches!(
+        reader.next(),
+        Some(Ok((Location { line: 2, .. }, _)))
+    ));
+    // the quoted amount spans lines 4 and 5, and the record is reported on the line it starts
+    assert!(matches!(
+        reader.next(),
+        Some(Ok((
+            Location { line: 4, .. },
+            PaymentCommand::Deposit { tx: 2, .. }
+        )))
+    ));
+    assert!(matches!(
+        reader.next(),
+        Some(Err(Error::Parse(ParseError(
+            Location { line: 6, .. },
+            PaymentCommandParseError::MissingData(_)
+        ))))
+    ));
+    assert!(reader.next().is_none());
+}
+
+#[test]
+fn stray_quotes() -> Result<(), crate::Error> {
+    // a quote in a comment does not carry the comment on to the rows after it
+    let input = "type,client,tx,amount\ndeposit,1,1,1.0\n# client said \"hi\ndeposit,1,2,5.0\ndeposit,1,3,7.0\n";
+
+    let mut engine = PaymentsEngine::new();
+    engine.run_from_reader(input.as_bytes())?;
+
+    assert_eq!(
+        engine.to_csv_string_sorted()?,
+        "client,available,held,total,locked\n1,13.0000,0.0000,13.0000,false"
+    );
+
+    // a quote inside an unquoted field is part of it, so only its own row is rejected
+    let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,5\"0\ndeposit,2,3,2.0\n";
+
+    let mut engine = PaymentsEngine::new();
+    let errors = engine.run_from_reader_with(input.as_bytes(), Mode::Lenient, |_, _, _| Ok(()))?;
+
+    assert!(matches!(
+        errors[..],
+        [Error::Parse(ParseError(
+            Location { line: 3, .. },
+            PaymentCommandParseError::ParseError(_)
+        ))]
+    ));
+    assert_eq!(
+        engine.to_csv_string_sorted()?,
+        "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n2,2.0000,0.0000,2.0000,false"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn overlong_records() {
+    let padding = "x".repeat(MAX_RECORD);
+
+    // a quoted field that is never closed, and a single overlong line, are each reported where they start
+    let input = format!(
+        "type,client,tx,amount\ndeposit,1,1,\"1.0\n{padding}\ndeposit,1,2,{padding}\ndeposit,1,3,1.0\n"
+    );
+    let mut reader = CommandReader::new(input.as_bytes()).unwrap();
+
+    assert!(matches!(
+        reader.next(),
+        Some(Err(Error::Parse(ParseError(
+            Location { line: 2, .. },
+            PaymentCommandParseError::ParseError(_)
+        ))))
+    ));
+    assert!(matches!(
+        reader.next(),
+        Some(Err(Error::Parse(ParseError(
+            Location { line: 4, .. },
+            PaymentCommandParseError::ParseError(_)
+        ))))
+    ));
+
+    // reading carries on from the line after, at the right offset
+    let (location, command) = reader.next().unwrap().unwrap();
+    assert_eq!(location.line, 5);
+    assert_eq!(location.offset, input.rfind("deposit").unwrap() as u64);
+    assert!(matches!(command, PaymentCommand::Deposit { tx: 3, .. }));
+    assert!(reader.next().is_none());
+}
+
+#[test]
+fn column_map() {
+    let columns = ColumnMap::from_header(&["Amount ", "tx", "currency", "type", "client"]).unwrap();
+
+    assert_eq!(columns.extra_columns(), ["currency"]);
+    assert_eq!(
+        columns.parse(&["1.5", "7", "EUR", "deposit", "3"]).unwrap(),
+        PaymentCommand::Deposit {
+            client: 3,
+            tx: 7,
+            amount: "1.5".parse().unwrap()
+        }
+    );
+    assert_eq!(
+        columns.parse(&["", "7", "", "dispute", "3"]).unwrap(),
+        PaymentCommand::Dispute { client: 3, tx: 7 }
+    );
+    assert!(matches!(
+        columns.parse(&["1.5", "7", "EUR", "deposit"]),
+        Err(PaymentCommandParseError::MissingData(_))
+    ));
+
+    assert_eq!(
+        ColumnMap::from_header(&["type", "client", "amount"]).unwrap_err(),
+        PaymentCommandParseError::MissingHeader("The CSV header has no tx column.".to_string())
+    );
+}
+
+#[test]
+fn error_locations() {
+    let err = PaymentsEngine::new()
+        .run_from_file("test_cases/t27_input.csv")
+        .unwrap_err();
+
+    let Error::Parse(ParseError(location, _)) = err else {
+        panic!("expected a parse error, got {:?}", err);
+    };
+    assert_eq!(
+        location,
+        Location {
+            file: Some("test_cases/t27_input.csv".into()),
+            line: 3,
+            offset: 39,
+        }
+    );
+    assert_eq!(
+        location.to_string(),
+        "test_cases/t27_input.csv, line 3, byte 39"
+    );
+
+    // skipped lines and a byte order mark still count towards the location
+    let input = "\u{feff}type,client,tx,amount\r\n# comment\r\n\r\ndeposit,1,1,1.0\r\n";
+    let mut reader = CommandReader::new(input.as_bytes()).unwrap();
+    let (location, _) = reader.next().unwrap().unwrap();
+    assert_eq!(
+        location,
+        Location {
+            file: None,
+            line: 4,
+            offset: 39,
+        }
+    );
+
+    // commands run from a slice are located by their position in it
+    let commands = [
+        PaymentCommand::Deposit {
+            client: 1,
+            tx: 1,
+            amount: Amount::from_raw(10_000),
+        },
+        PaymentCommand::Deposit {
+            client: 2,
+            tx: 1,
+            amount: Amount::from_raw(10_000),
+        },
+    ];
+    assert!(matches!(
+        PaymentsEngine::new().run_commands(&commands),
+        Err(RuntimeError(
+            Location { line: 2, .. },
+            EngineError::DuplicateTransaction(1)
+        ))
+    ));
+}
+
+#[test]
+fn history_log() -> Result<(), crate::Error> {
+    let commands = CommandReader::with_file(
+        std::fs::File::open("test_cases/t2_input.csv").map(std::io::BufReader::new)?,
+        "t2_input.csv",
+        AmountLimits::default(),
+    )?;
+
+    let mut buf = Vec::new();
+    let mut history = HistoryWriter::new(&mut buf)?;
+
+    PaymentsEngine::new().run_commands_with(
+        commands,
+        Mode::Strict,
+        |location, command, outcome| match command.client() {
+            2 => history.write(location, command, outcome),
+            _ => Ok(()),
+        },
+    )?;
+
+    assert_eq!(
+        String::from_utf8(buf).unwrap(),
+        "file,line,type,client,tx,amount,outcome
+t2_input.csv,3,deposit,2,2,2.0000,applied
+t2_input.csv,6,withdrawal,2,5,3.0000,ignored: insufficient funds
+"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn output_formats() -> Result<(), crate::Error> {
+    let mut engine = PaymentsEngine::new();
+    engine.run_from_file("test_cases/t16_input.csv")?;
+
+    let write = |format: OutputFormat| -> Result<String, crate::Error> {
+        let mut buf = Vec::new();
+        engine.write_accounts(format.writer(&mut buf).as_mut(), AccountOrder::ClientId)?;
+        Ok(String::from_utf8(buf).unwrap())
+    };
+
+    assert_eq!(
+        write(OutputFormat::Csv)?,
+        engine.to_csv_string_sorted()? + "\n"
+    );
+    assert_eq!(
+        write(OutputFormat::JsonLines)?,
+        r#"{"client":1,"available":2.0000,"held":0.0000,"total":2.0000,"locked":true}
+{"client":2,"available":3.0000,"held":0.0000,"total":3.0000,"locked":false}
+"#
+    );
+    assert_eq!(
+        write(OutputFormat::Table)?,
+        "client | available |   held |  total | locked
+-------+-----------+--------+--------+-------
+     1 |    2.0000 | 0.0000 | 2.0000 |   true
+     2 |    3.0000 | 0.0000 | 3.0000 |  false
+"
+    );
+
+    // every record is the same width, whatever the values
+    let fixed = write(OutputFormat::FixedWidth)?;
+    assert!(fixed.lines().all(|line| line.len() == 82));
+    assert!(fixed.starts_with("         1                2.0000"));
+
+    for format in ["csv", "jsonl", "table", "fixed"] {
+        assert_eq!(format.parse::<OutputFormat>().unwrap().to_string(), format);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn account_order() -> Result<(), crate::Error> {
+    let input = "type,client,tx,amount
+deposit,3,1,1.0
+deposit,1,2,5.0
+withdrawal,7,3,1.0
+deposit,2,4,1.0
+";
+
+    let mut engine = PaymentsEngine::new();
+    engine.run_from_reader(input.as_bytes())?;
+
+    let ids = |order| {
+        engine
+            .accounts_in_order(order)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>()
+    };
+
+    // a declined withdrawal does not create an account
+    assert_eq!(ids(AccountOrder::ClientId), [1, 2, 3]);
+    assert_eq!(ids(AccountOrder::Total), [1, 2, 3]);
+    assert_eq!(ids(AccountOrder::Insertion), [3, 1, 2]);
+    assert_eq!(engine.to_csv_string()?, engine.to_csv_string_sorted()?);
+
+    Ok(())
+}
+
+#[test]
+fn snapshot_round_trip() -> Result<(), crate::Error> {
+    let config = EngineConfig {
+        withdrawal_disputes: WithdrawalDisputePolicy::ReverseFlow,
+        ..Default::default()
+    };
+
+    let mut engine = PaymentsEngine::with_config(config);
+    engine.run_from_file("test_cases/t22_input.csv")?;
+
+    let mut snapshot = Vec::new();
+    engine.write_snapshot(&mut snapshot)?;
+
+    let mut restored = PaymentsEngine::read_snapshot(snapshot.as_slice(), config)?;
+    assert_eq!(
+        restored.to_csv_string_sorted()?,
+        engine.to_csv_string_sorted()?
+    );
+
+    // the same state always gives the same snapshot, but for the order of the transactions
+    let mut rewritten = Vec::new();
+    restored.write_snapshot(&mut rewritten)?;
+    let lines = |snapshot: Vec<u8>| {
+        let mut lines: Vec<_> = String::from_utf8(snapshot)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        lines.sort();
+        lines
+    };
+    assert_eq!(lines(rewritten), lines(snapshot));
+
+    // transactions from before the snapshot can still be disputed, and their ids are still taken
+    let next_day = "type,client,tx,amount
+dispute,1,1,
+deposit,1,1,5.0
+";
+    assert!(matches!(
+        restored.run_from_reader(next_day.as_bytes()),
+        Err(Error::Runtime(RuntimeError(
+            Location { line: 3, .. },
+            EngineError::DuplicateTransaction(1)
+        )))
+    ));
+    assert_eq!(
+        restored.get_transaction(1)?.map(|x| x.status()),
+        Some(TransactionStatus::Disputed)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn invalid_snapshots() {
+    let read = |snapshot: &str| {
+        PaymentsEngine::read_snapshot(snapshot.as_bytes(), EngineConfig::default())
+    };
+
+    assert!(matches!(
+        read("client,available,held,total,locked\n"),
+        Err(Error::Snapshot(SnapshotError(Location { line: 1, .. }, _)))
+    ));
+    assert!(matches!(
+        read("# payments-engine snapshot v2\njournaled,0\n[accounts]\nclient,available,held,locked\n1,1.0000,0.0000,false\n1,1.0000,0.0000,false\n"),
+        Err(Error::Snapshot(SnapshotError(Location { line: 6, .. }, _)))
+    ));
+    assert!(matches!(
+        read("# payments-engine snapshot v2\njournaled,0\n[accounts]\nclient,available,held,locked\n[transactions]\ntx,client,type,amount,status\n1,1,deposit,1.0000,pending\n"),
+        Err(Error::Snapshot(SnapshotError(Location { line: 7, offset: 126, .. }, _)))
+    ));
+    assert!(matches!(
+        read("# payments-engine snapshot v2\njournaled,0\n[accounts]\nclient,available,held,locked\n1,1.0000,0.0000,false\n"),
+        Err(Error::Snapshot(SnapshotError(Location { line: 5, .. }, _)))
+    ));
+    assert!(matches!(
+        read("# payments-engine snapshot v2\n[accounts]\n"),
+        Err(Error::Snapshot(SnapshotError(Location { line: 2, .. }, _)))
+    ));
+    assert!(matches!(
+        read("# payments-engine snapshot v1\n[accounts]\n"),
+        Err(Error::Snapshot(SnapshotError(Location { line: 1, .. }, _)))
+    ));
+
+    // a snapshot that cannot be read or opened is a snapshot error too, rather than a bare IO error
+    assert!(matches!(
+        PaymentsEngine::read_snapshot(
+            b"# payments-engine snapshot v2\njournaled,0\n[acc\xffounts]\n".as_slice(),
+            EngineConfig::default()
+        ),
+        Err(Error::Snapshot(SnapshotError(
+            Location {
+                line: 3,
+                offset: 42,
+                ..
+            },
+            _
+        )))
+    ));
+    assert!(matches!(
+        PaymentsEngine::load_snapshot("test_cases/missing.snapshot", EngineConfig::default()),
+        Err(Error::Snapshot(SnapshotError(
+            Location {
+                line: 0,
+                file: Some(_),
+                ..
+            },
+            _
+        )))
+    ));
+}
+
+#[test]
+fn journal_recovery() -> Result<(), crate::Error> {
+    let path = std::env::temp_dir().join(format!("payments-engine-{}.journal", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+
+    let input = "type,client,tx,amount
+deposit,1,1,10.0
+withdrawal,1,2,20.0
+deposit,2,3,5.0
+dispute,2,1,
+";
+    let run = |engine: &mut PaymentsEngine| {
+        let commands =
+            CommandReader::with_file(input.as_bytes(), "input.csv", AmountLimits::default())?;
+        engine.run_commands_with(commands, Mode::Lenient, |_, _, _| Ok(()))
+    };
+
+    let mut engine = PaymentsEngine::new();
+    engine.open_journal(path)?;
+    assert_eq!(run(&mut engine)?.len(), 1);
+    drop(engine);
+
+    let journal = std::fs::read_to_string(path).unwrap();
+    assert_eq!(
+        journal,
+        "# payments-engine journal v2
+journaled,0
+file,line,type,client,tx,amount,result
+input.csv,2,deposit,1,1,10.0000,applied
+input.csv,3,withdrawal,1,2,20.0000,ignored: insufficient funds
+input.csv,4,deposit,2,3,5.0000,applied
+input.csv,5,dispute,2,1,,error: Client Id Mismatch: 2 != 1
+"
+    );
+
+    // a crash while appending leaves a torn last entry, which is dropped
+    let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+    std::io::Write::write_all(&mut file, b"extra.csv,2,deposit,3,4").unwrap();
+    drop(file);
+
+    let mut recovered = PaymentsEngine::new();
+    recovered.open_journal(path)?;
+    assert_eq!(std::fs::read_to_string(path).unwrap(), journal);
+    assert_eq!(recovered.to_csv_string()?, "client,available,held,total,locked\n1,10.0000,0.0000,10.0000,false\n2,5.0000,0.0000,5.0000,false");
+    assert_eq!(
+        recovered.get_transaction(2)?.map(|x| x.status()),
+        Some(TransactionStatus::Declined)
+    );
+
+    // running the same input again skips the rows already journaled
+    assert!(run(&mut recovered)?.is_empty());
+    assert_eq!(std::fs::read_to_string(path).unwrap(), journal);
+    assert_eq!(recovered.to_csv_string()?, "client,available,held,total,locked\n1,10.0000,0.0000,10.0000,false\n2,5.0000,0.0000,5.0000,false");
+
+    // a file of the same name with different rows is refused, rather than having its rows skipped
+    let changed = input.replace("deposit,2,3,5.0", "deposit,2,3,7.0");
+    let commands =
+        CommandReader::with_file(changed.as_bytes(), "input.csv", AmountLimits::default())?;
+    assert!(matches!(
+        recovered.run_commands_with(commands, Mode::Lenient, |_, _, _| Ok(())),
+        Err(Error::Journal(JournalError(Location { line: 4, .. }, _)))
+    ));
+
+    // once a snapshot holds its commands, the journal is emptied, and the same rows are run afresh
+    recovered.checkpoint_journal()?;
+    assert_eq!(
+        std::fs::read_to_string(path).unwrap(),
+        "# payments-engine journal v2\njournaled,4\nfile,line,type,client,tx,amount,result\n"
+    );
+    let commands =
+        CommandReader::with_file(changed.as_bytes(), "input.csv", AmountLimits::default())?;
+    assert!(matches!(
+        recovered.run_commands_with(commands, Mode::Strict, |_, _, _| Ok(())),
+        Err(Error::Runtime(RuntimeError(
+            Location { line: 2, .. },
+            EngineError::DuplicateTransaction(1)
+        )))
+    ));
+
+    // a journal that does not replay to the same results is refused
+    std::fs::write(
+        path,
+        journal.replace("ignored: insufficient funds", "applied"),
+    )
+    .unwrap();
+    assert!(matches!(
+        PaymentsEngine::new().open_journal(path),
+        Err(Error::Journal(JournalError(
+            Location {
+                line: 5,
+                offset: 120,
+                ..
+            },
+            _
+        )))
+    ));
+
+    std::fs::remove_file(path).unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn journal_after_snapshot() -> Result<(), crate::Error> {
+    let path = |kind: &str| {
+        let path = std::env::temp_dir().join(format!(
+            "payments-engine-{}-checkpoint.{kind}",
+            std::process::id()
+        ));
+        path.to_str().unwrap().to_string()
+    };
+    let (journal, snapshot) = (path("journal"), path("snapshot"));
+    let _ = std::fs::remove_file(&journal);
+
+    let run = |engine: &mut PaymentsEngine, name: &str, input: &str| {
+        let commands = CommandReader::with_file(input.as_bytes(), name, AmountLimits::default())?;
+        engine.run_commands_with(commands, Mode::Strict, |_, _, _| Ok(()))
+    };
+    let monday = "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,5.0\n";
+    let tuesday = "type,client,tx,amount\nwithdrawal,1,3,4.0\n";
+    let accounts = "client,available,held,total,locked\n1,6.0000,0.0000,6.0000,false\n2,5.0000,0.0000,5.0000,false";
+
+    // the process stops after the snapshot is saved, but before the journal is emptied
+    let mut engine = PaymentsEngine::new();
+    engine.open_journal(&journal)?;
+    run(&mut engine, "monday.csv", monday)?;
+    engine.save_snapshot(&snapshot)?;
+    drop(engine);
+
+    // the entries the snapshot holds are skipped, as are their rows when the same file is run again
+    let mut restored = PaymentsEngine::load_snapshot(&snapshot, EngineConfig::default())?;
+    restored.open_journal(&journal)?;
+    run(&mut restored, "monday.csv", monday)?;
+    run(&mut restored, "tuesday.csv", tuesday)?;
+    assert_eq!(restored.to_csv_string()?, accounts);
+    drop(restored);
+
+    // after another crash, only the entries since the snapshot are replayed
+    let mut restored = PaymentsEngine::load_snapshot(&snapshot, EngineConfig::default())?;
+    restored.open_journal(&journal)?;
+    assert_eq!(restored.to_csv_string()?, accounts);
+
+    // once checkpointed, the journal continues from the new snapshot
+    restored.save_snapshot(&snapshot)?;
+    restored.checkpoint_journal()?;
+    assert_eq!(
+        std::fs::read_to_string(&journal).unwrap(),
+        "# payments-engine journal v2\njournaled,3\nfile,line,type,client,tx,amount,result\n"
+    );
+    drop(restored);
+
+    let mut restored = PaymentsEngine::load_snapshot(&snapshot, EngineConfig::default())?;
+    restored.open_journal(&journal)?;
+    assert_eq!(restored.to_csv_string()?, accounts);
+
+    // a journal that continues from a snapshot cannot be opened without it
+    assert!(matches!(
+        PaymentsEngine::new().open_journal(&journal),
+        Err(Error::Journal(JournalError(Location { line: 2, .. }, _)))
+    ));
+
+    std::fs::remove_file(&journal).unwrap();
+    std::fs::remove_file(&snapshot).unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn disk_store() -> Result<(), crate::Error> {
+    const COUNT: TransactionId = 5_000;
+
+    let (accounts, transactions) = disk_stores("disk_store")?;
+    let mut engine = PaymentsEngine::with_stores(EngineConfig::default(), accounts, transactions);
+
+    // enough transactions for the table to grow several times
+    for tx in 1..=COUNT {
+        engine
+            .run_command(PaymentCommand::Deposit {
+                client: (tx % 3) as ClientId,
+                tx,
+                amount: Amount::from_raw(1),
+            })
+            .unwrap();
+    }
+    engine
+        .run_command(PaymentCommand::Dispute { client: 2, tx: 5 })
+        .unwrap();
+
+    assert_eq!(
+        engine.get_transaction(5)?.map(|x| x.status()),
+        Some(TransactionStatus::Disputed)
+    );
+    assert!(engine.get_transaction(COUNT + 1)?.is_none());
+    assert_eq!(engine.transactions().count(), COUNT as usize);
+
+    let mut snapshot = Vec::new();
+    engine.write_snapshot(&mut snapshot)?;
+    let restored = PaymentsEngine::read_snapshot(snapshot.as_slice(), EngineConfig::default())?;
+
+    assert_eq!(restored.to_csv_string()?, engine.to_csv_string()?);
+    assert_eq!(
+        engine.to_csv_string_ordered(AccountOrder::Insertion)?,
+        "client,available,held,total,locked
+1,0.1667,0.0000,0.1667,false
+2,0.1666,0.0001,0.1667,false
+0,0.1666,0.0000,0.1666,false"
+    );
+
+    // the stores remove their files once dropped
+    let path = std::env::temp_dir().join(format!(
+        "payments-engine-{}-disk_store.transactions",
+        std::process::id()
+    ));
+    assert!(path.exists());
+    drop(engine);
+    assert!(!path.exists());
+
+    Ok(())
+}
+
+#[test]
+fn failed_commits() -> Result<(), crate::Error> {
+    use std::{cell::Cell, collections::HashMap, io, rc::Rc};
+
+    // a transaction store whose writes fail while `failing` is set
+    struct FailingStore {
+        transactions: HashMap<TransactionId, TransactionData>,
+        failing: Rc<Cell<bool>>,
+    }
+
+    impl TransactionStore for FailingStore {
+        fn get(&self, tx: TransactionId) -> io::Result<Option<TransactionData>> {
+            TransactionStore::get(&self.transactions, tx)
+        }
+
+        fn insert(
+            &mut self,
+            tx: TransactionId,
+            data: TransactionData,
+        ) -> io::Result<Option<TransactionData>> {
+            if self.failing.get() {
+                return Err(io::Error::other("disk full"));
+            }
+
+            TransactionStore::insert(&mut self.transactions, tx, data)
+        }
+
+        fn iter(
+            &self,
+        ) -> Box<dyn Iterator<Item = io::Result<(TransactionId, TransactionData)>> + '_> {
+            TransactionStore::iter(&self.transactions)
+        }
+    }
+
+    let failing = Rc::new(Cell::new(false));
+    let store = FailingStore {
+        transactions: HashMap::new(),
+        failing: failing.clone(),
+    };
+    let mut engine = PaymentsEngine::with_stores(EngineConfig::default(), HashMap::new(), store);
+    let deposit = |client, tx| PaymentCommand::Deposit {
+        client,
+        tx,
+        amount: Amount::from_raw(10_000),
+    };
+
+    engine.run_command(deposit(1, 1)).unwrap();
+
+    // a deposit whose transaction cannot be stored leaves the account as it was, and a new client unlisted
+    failing.set(true);
+    assert!(matches!(
+        engine.run_command(deposit(1, 2)),
+        Err(EngineError::Storage(_))
+    ));
+    assert!(matches!(
+        engine.run_command(deposit(2, 3)),
+        Err(EngineError::Storage(_))
+    ));
+    assert_eq!(
+        engine.to_csv_string_ordered(AccountOrder::Insertion)?,
+        "client,available,held,total,locked
+1,1.0000,0.0000,1.0000,false"
+    );
+
+    // the same deposits succeed once the store recovers, with the new client listed once
+    failing.set(false);
+    engine.run_command(deposit(2, 3)).unwrap();
+    engine.run_command(deposit(2, 4)).unwrap();
+    engine.run_command(deposit(1, 2)).unwrap();
+    assert_eq!(
+        engine.to_csv_string_ordered(AccountOrder::Insertion)?,
+        "client,available,held,total,locked
+1,2.0000,0.0000,2.0000,false
+2,2.0000,0.0000,2.0000,false"
+    );
+
+    Ok(())
+}
+
+// removing the file of a store makes its next rebuild fail, which only Unix allows while the file is open
+#[cfg(unix)]
+#[test]
+fn failed_disk_store_growth() {
+    let path = std::env::temp_dir().join(format!(
+        "payments-engine-{}-failed_growth.transactions",
+        std::process::id()
+    ));
+    let mut grow_path = path.clone().into_os_string();
+    grow_path.push(".grow");
+
+    let mut store = DiskTransactionStore::create(&path).unwrap();
+    let data = TransactionData::new(
+        1,
+        TransactionKind::Deposit,
+        Amount::from_raw(1),
+        TransactionStatus::Ok,
+    );
+
+    // three quarters of the initial capacity, so the next insert grows the table
+    for tx in 0..768 {
+        TransactionStore::insert(&mut store, tx, data).unwrap();
+    }
+
+    std::fs::remove_file(&path).unwrap();
+    assert!(TransactionStore::insert(&mut store, 768, data).is_err());
+    assert!(!std::path::Path::new(&grow_path).exists());
+}
+
+#[test]
+fn parallel_matches_sequential() -> Result<(), crate::Error> {
+    // many clients, with duplicate ids and disputes naming the transactions of other clients
+    let mut input = String::from("type,client,tx,amount\n");
+    for i in 1..=5_000u32 {
+        let client = i % 37;
+        let row = match i % 7 {
+            0 => format!("withdrawal,{client},{i},3.5"),
+            1 => format!("dispute,{client},{},", i - 1),
+            2 => format!("resolve,{},{},", client + 1, i - 2),
+            3 => format!("chargeback,{client},{},", i - 2),
+            4 => format!("deposit,{client},{},1", i / 2),
+            5 => format!("withdrawal,{client},{i},abc"),
+            _ => format!("deposit,{client},{i},10"),
+        };
+        input.push_str(&row);
+        input.push('\n');
+    }
+
+    let run = |parallel: bool| -> Result<_, crate::Error> {
+        let mut engine = PaymentsEngine::new();
+        let mut outcomes = Vec::new();
+        let commands = CommandReader::new(input.as_bytes())?;
+        let on_outcome = |location: &Location, _: &PaymentCommand, outcome| {
+            outcomes.push((location.line, outcome));
+            Ok(())
+        };
+
+        let errors = if parallel {
+            engine.run_commands_parallel(
+                commands,
+                std::num::NonZeroUsize::new(3).unwrap(),
+                Mode::Lenient,
+                on_outcome,
+            )?
+        } else {
+            engine.run_commands_with(commands, Mode::Lenient, on_outcome)?
+        };
+
+        let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+        let accounts = engine.to_csv_string_ordered(AccountOrder::Insertion)?;
+
+        Ok((accounts, outcomes, errors))
+    };
+
+    let sequential = run(false)?;
+    let parallel = run(true)?;
+
+    assert!(!sequential.2.is_empty());
+    assert_eq!(sequential, parallel);
+
+    // a strict run fails on the same row
+    let strict = |parallel: bool| {
+        let mut engine = PaymentsEngine::new();
+        let commands = CommandReader::new(input.as_bytes()).unwrap();
+
+        let result = if parallel {
+            engine.run_commands_parallel(
+                commands,
+                std::num::NonZeroUsize::new(3).unwrap(),
+                Mode::Strict,
+                |_, _, _| Ok(()),
+            )
+        } else {
+            engine.run_commands_with(commands, Mode::Strict, |_, _, _| Ok(()))
+        };
+
+        result.unwrap_err().to_string()
+    };
+
+    assert_eq!(strict(false), strict(true));
+
+    Ok(())
+}
+
+// takes the next item of a stream, without needing an extension trait
+#[cfg(feature = "async")]
+async fn next<S: futures_core::Stream + Unpin>(stream: &mut S) -> Option<S::Item> {
+    std::future::poll_fn(|cx| std::pin::Pin::new(&mut *stream).poll_next(cx)).await
+}
+
+#[cfg(feature = "async")]
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn async_reader_matches_reader() {
+    let input = "\u{feff}type, client ,tx,amount\r\n\
+                 deposit,1,1,10\r\n\
+                 \n\
+                 # a comment\n\
+                 \"with\ndrawal\",1,2,1\n\
+                 withdrawal,1,3,2.5\n\
+                 dispute,1,1,\n\
+                 deposit,2,4,\"1\n\
+                 \n\
+                 deposit,2,5,3";
+
+    let expected: Vec<_> = CommandReader::new(input.as_bytes())
+        .unwrap()
+        .map(|x| format!("{x:?}"))
+        .collect();
+
+    let read = block_on(async {
+        // a tiny buffer, so that lines arrive over several reads
+        let reader = tokio::io::BufReader::with_capacity(3, input.as_bytes());
+        let mut commands = crate::AsyncCommandReader::new(reader).await.unwrap();
+        let mut read = Vec::new();
+
+        while let Some(command) = next(&mut commands).await {
+            read.push(format!("{command:?}"));
+        }
+
+        read
+    });
+
+    // the unterminated quote runs to the end of the input
+    assert_eq!(expected.len(), 5);
+    assert_eq!(expected, read);
+
+    let missing = block_on(crate::AsyncCommandReader::new("".as_bytes()));
+    assert!(matches!(missing, Err(Error::Parse(_))));
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn async_overlong_records() {
+    let padding = "x".repeat(MAX_RECORD);
+    let input = format!(
+        "type,client,tx,amount\ndeposit,1,1,\"1.0\n{padding}\ndeposit,1,2,{padding}\ndeposit,1,3,1.0\n"
+    );
+
+    let expected: Vec<_> = CommandReader::new(input.as_bytes())
+        .unwrap()
+        .map(|x| format!("{x:?}"))
+        .collect();
+
+    let read = block_on(async {
+        let reader = tokio::io::BufReader::with_capacity(1000, input.as_bytes());
+        let mut commands = crate::AsyncCommandReader::new(reader).await.unwrap();
+        let mut read = Vec::new();
+
+        while let Some(command) = next(&mut commands).await {
+            read.push(format!("{command:?}"));
+        }
+
+        read
+    });
+
+    // both overlong records are reported, and reading carries on from the line after them
+    assert_eq!(expected.len(), 3);
+    assert_eq!(expected, read);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn run_stream() -> Result<(), crate::Error> {
+    use std::{
+        cell::Cell,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    // a stream of commands from an iterator, counting the commands taken
+    struct Commands<'a, I>(I, &'a Cell<usize>);
+
+    impl<I: Iterator + Unpin> futures_core::Stream for Commands<'_, I> {
+        type Item = I::Item;
+
+        fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<I::Item>> {
+            self.1.set(self.1.get() + 1);
+            Poll::Ready(self.0.next())
+        }
+    }
+
+    let input = "type,client,tx,amount
+deposit,1,1,10
+withdrawal,1,2,20
+deposit,2,1,5
+dispute,1,1,
+chargeback,1,1,
+deposit,1,3,1";
+
+    let mut expected = Vec::new();
+    let mut sequential = PaymentsEngine::new();
+    let errors = sequential.run_from_reader_with(
+        input.as_bytes(),
+        Mode::Lenient,
+        |location, _, outcome| {
+            expected.push(Ok((location.line, outcome)));
+            Ok(())
+        },
+    )?;
+    for e in errors {
+        let Error::Runtime(RuntimeError(location, _)) = &e else {
+            panic!("expected a runtime error");
+        };
+        expected.push(Err(location.line));
+    }
+    expected.sort_by_key(|x| *x.as_ref().map(|(line, _)| line).unwrap_or_else(|line| line));
+
+    let mut engine = PaymentsEngine::new();
+    let taken = Cell::new(0);
+    let outcomes = block_on(async {
+        let commands = Commands(CommandReader::new(input.as_bytes()).unwrap(), &taken);
+        let mut outcomes = engine.run_stream(commands);
+        let mut results = Vec::new();
+
+        // commands are only taken as their outcomes are asked for
+        results.push(next(&mut outcomes).await.unwrap());
+        assert_eq!(taken.get(), 1);
+
+        while let Some(outcome) = next(&mut outcomes).await {
+            results.push(outcome);
+        }
+
+        results
+    });
+
+    let outcomes: Vec<_> = outcomes
+        .into_iter()
+        .map(|x| match x {
+            Ok((location, _, outcome)) => Ok((location.line, outcome)),
+            Err(Error::Runtime(RuntimeError(location, _))) => Err(location.line),
+            Err(e) => panic!("unexpected error {e}"),
+        })
+        .collect();
+
+    assert_eq!(expected, outcomes);
+    assert_eq!(sequential.to_csv_string()?, engine.to_csv_string()?);
+
+    Ok(())
+}
+
+// starts a server on a free port of localhost, which runs until the tests end
+fn start_server(
+    config: crate::ServerConfig,
+    serve: fn(&crate::Server, std::net::TcpListener),
+) -> std::net::SocketAddr {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = crate::Server::new(PaymentsEngine::new(), config);
+
+    std::thread::spawn(move || serve(&server, listener));
+
+    address
+}
+
+// writes `input` to a connection, then reads everything written back until the server closes it
+fn send(mut stream: std::net::TcpStream, input: &str) -> String {
+    use std::io::{Read, Write};
+
+    stream.write_all(input.as_bytes()).unwrap();
+    stream.shutdown(std::net::Shutdown::Write).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    response
+}
+
+#[test]
+fn server_concurrent_connections() {
+    use std::{net::TcpStream, sync::Barrier};
+
+    const CONNECTIONS: u16 = 1000;
+
+    let address = start_server(crate::ServerConfig::default(), |server, listener| {
+        server.serve(listener, |_| {})
+    });
+    let barrier = Barrier::new(CONNECTIONS as usize);
+
+    // connections are opened one after another, as a burst of them would overflow the backlog of the listener
+    let streams: Vec<_> = (1..=CONNECTIONS)
+        .map(|client| {
+            let stream = TcpStream::connect(address).unwrap();
+            // let the server accept it, even with a single core
+            std::thread::yield_now();
+
+            (client, stream)
+        })
+        .collect();
+
+    // every connection is open before any of them sends a row
+    std::thread::scope(|scope| {
+        for (client, stream) in streams {
+            let barrier = &barrier;
+
+            scope.spawn(move || {
+                barrier.wait();
+
+                let tx = client as TransactionId * 10;
+                let input = format!(
+                    "type,client,tx,amount\n\
+                     deposit,{client},{},{client}\n\
+                     withdrawal,{client},{},1\n\
+                     deposit,{client},{},0.5\n",
+                    tx,
+                    tx + 1,
+                    tx + 2
+                );
+                let response = send(stream, &input);
+
+                assert!(response.ends_with(": 3 commands, 0 errors\n"), "{response}");
+            });
+        }
+    });
+
+    let stream = TcpStream::connect(address).unwrap();
+    let accounts = send(stream, "accounts\n");
+
+    let mut expected = String::from("client,available,held,total,locked\n");
+    for client in 1..=CONNECTIONS {
+        let total = format!("{}.5000", client - 1);
+        expected.push_str(&format!("{client},{total},0.0000,{total},false\n"));
+    }
+
+    assert_eq!(accounts, expected);
+}
+
+#[test]
+fn server_errors() {
+    use std::net::TcpStream;
+
+    let input = "type,client,tx,amount
+deposit,1,1,10
+deposit,2,1,5
+withdrawal,1,2,oops
+withdrawal,1,3,4
+";
+
+    // a lenient connection is told of every error once it is done
+    let address = start_server(
+        crate::ServerConfig {
+            mode: Mode::Lenient,
+            ..Default::default()
+        },
+        |server, listener| server.serve(listener, |_| {}),
+    );
+    let stream = TcpStream::connect(address).unwrap();
+    let peer = stream.local_addr().unwrap();
+    let response = send(stream, input);
+
+    assert_eq!(
+        response,
+        format!(
+            "[{peer}, line 3, byte 37] Duplicate Transaction: 1
+[{peer}, line 4, byte 51] Parse Error: Could not parse amount: invalid digit found in string
+{peer}: 2 commands, 2 errors
+"
+        )
+    );
+
+    // a strict connection is only told of the first
+    let address = start_server(crate::ServerConfig::default(), |server, listener| {
+        server.serve(listener, |_| {})
+    });
+    let stream = TcpStream::connect(address).unwrap();
+    let peer = stream.local_addr().unwrap();
+    let response = send(stream, input);
+
+    assert_eq!(
+        response,
+        format!("[{peer}, line 3, byte 37] Duplicate Transaction: 1\n")
+    );
+
+    let stream = TcpStream::connect(address).unwrap();
+    assert_eq!(
+        send(stream, "accounts\n"),
+        "client,available,held,total,locked\n1,10.0000,0.0000,10.0000,false\n"
+    );
+}
+
+#[test]
+fn server_limits() {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        time::Duration,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = crate::Server::new(
+        PaymentsEngine::new(),
+        crate::ServerConfig {
+            timeout: Duration::from_millis(200),
+            ..Default::default()
+        },
+    );
+    let (errors, reported) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        server.serve(listener, move |e| {
+            let _ = errors.send(e.to_string());
+        })
+    });
+
+    // a connection that stalls part way through a line is dropped, and reported
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    stream.write_all(b"type,client").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert_eq!(response, "");
+    assert!(reported.recv_timeout(Duration::from_secs(10)).is_ok());
+
+    // an overlong line is an error rather than being buffered
+    let stream = TcpStream::connect(address).unwrap();
+    let input = format!(
+        "type,client,tx,amount\ndeposit,1,1,{}\n",
+        "1".repeat(1024 * 1024)
+    );
+    assert!(send(stream, &input).contains("The record is longer than 65536 bytes."));
+
+    // a failed connection that keeps sending is closed once a bounded amount has been discarded
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(b"type,client,tx,amount\n").unwrap();
+    stream.write_all(b"deposit,1,1,oops\n").unwrap();
+    let chunk = [b'#'; 64 * 1024];
+    let sent = (0..1024)
+        .take_while(|_| stream.write_all(&chunk).is_ok())
+        .count();
+    assert!(sent < 1024, "{sent}");
+}
+
+#[test]
+fn http_timeouts() {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        time::Duration,
+    };
+
+    let config = crate::ServerConfig {
+        timeout: Duration::from_millis(200),
+        ..Default::default()
+    };
+    let address = start_server(config, |server, listener| {
+        server.serve_http(listener, |_| {})
+    });
+
+    // requests that stall part way through the head or the body are answered, without waiting for the rest
+    for partial in [
+        "POST /transactions HTTP/1.1\r\nContent-Le",
+        "POST /transactions HTTP/1.1\r\nContent-Length: 100\r\n\r\n{\"type\"",
+    ] {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        stream.write_all(partial.as_bytes()).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 "), "{response}");
+    }
+}
+
+#[test]
+fn http_api() {
+    use std::net::TcpStream;
+
+    let address = start_server(crate::ServerConfig::default(), |server, listener| {
+        server.serve_http(listener, |_| {})
+    });
+
+    // sends a request, returning the status and body of the response
+    let request = |method: &str, path: &str, body: &str| -> (u16, String) {
+        let request = format!(
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let response = send(TcpStream::connect(address).unwrap(), &request);
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+
+        (status, body.to_string())
+    };
+    let post = |body: &str| request("POST", "/transactions", body);
+
+    assert_eq!(
+        post(r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "10.5"}"#),
+        (200, r#"{"result":"applied"}"#.to_string())
+    );
+    assert_eq!(
+        post(r#"{"type":"withdrawal","client":1,"tx":2,"amount":20}"#),
+        (
+            200,
+            r#"{"result":"ignored","reason":"insufficient funds"}"#.to_string()
+        )
+    );
+    assert_eq!(
+        post(r#"{"type":"dep\u006fsit","client":2,"tx":1,"amount":1}"#),
+        (422, r#"{"error":"Duplicate Transaction: 1"}"#.to_string())
+    );
+    assert_eq!(
+        post(r#"{"type":"dispute","client":1,"tx":1,"amount":null,"note":"chargeback \"soon\""}"#),
+        (200, r#"{"result":"applied"}"#.to_string())
+    );
+
+    // malformed commands are rejected before they reach the engine
+    assert_eq!(post(r#"{"type":"deposit","client":1}"#).0, 400);
+    assert_eq!(
+        post(r#"{"type":"deposit","client":1,"tx":5,"amount":-1}"#).0,
+        400
+    );
+    assert_eq!(
+        post(r#"{"type":"deposit","client":1,"tx":5,"amount":1"#).0,
+        400
+    );
+    assert_eq!(post("type,client,tx,amount").0, 400);
+
+    assert_eq!(
+        request("GET", "/clients/1", ""),
+        (
+            200,
+            r#"{"client":1,"available":0.0000,"held":10.5000,"total":10.5000,"locked":false}"#
+                .to_string()
+        )
+    );
+    assert_eq!(
+        request("GET", "/transactions/1?fields=all", ""),
+        (
+            200,
+            r#"{"tx":1,"client":1,"type":"deposit","amount":10.5000,"status":"disputed"}"#
+                .to_string()
+        )
+    );
+    assert_eq!(
+        request("GET", "/transactions/2", "").1,
+        r#"{"tx":2,"client":1,"type":"withdrawal","amount":20.0000,"status":"declined"}"#
+    );
+
+    assert_eq!(request("GET", "/clients/2", "").0, 404);
+    assert_eq!(request("GET", "/clients/x", "").0, 400);
+    assert_eq!(request("GET", "/transactions", "").0, 405);
+    assert_eq!(request("DELETE", "/clients/1", "").0, 405);
+    assert_eq!(request("GET", "/", "").0, 404);
 }
@@ -0,0 +1,143 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::error::EngineError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionKind {
+    Deposit,
+    Withdrawal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionStatus {
+    Ok,
+    Disputed,
+    Resolved,
+    Chargeback,
+    // the transaction was not applied, but is kept so its id cannot be reused
+    Declined,
+}
+
+/// An event that moves a transaction from one `TransactionStatus` to another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionEvent {
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+impl TransactionEvent {
+    /// The status a transaction ends up in if this event is applied to it.
+    pub fn target(self) -> TransactionStatus {
+        match self {
+            TransactionEvent::Dispute => TransactionStatus::Disputed,
+            TransactionEvent::Resolve => TransactionStatus::Resolved,
+            TransactionEvent::Chargeback => TransactionStatus::Chargeback,
+        }
+    }
+}
+
+/// Whether a transaction whose dispute was resolved can be disputed again.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RedisputePolicy {
+    #[default]
+    Deny,
+    Allow,
+}
+
+/// How a dispute, resolve or chargeback that is not a legal transition from the status of its transaction is handled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionPolicy {
+    /// The command is ignored, with the reason it could not be applied, such as a dispute repeated by a partner.
+    #[default]
+    Ignore,
+    /// The command fails with `EngineError::InvalidTransition`.
+    Reject,
+}
+
+impl TransactionStatus {
+    /// Applies `event` to a transaction in this status, returning its new status.
+    ///
+    /// The legal transitions are:
+    ///
+    /// - `Ok` to `Disputed`
+    /// - `Disputed` to `Resolved` or `Chargeback`
+    /// - `Resolved` to `Disputed`, only if `redisputes` is `RedisputePolicy::Allow`
+    ///
+    /// `Chargeback` and `Declined` are final.
+    ///
+    /// # Errors
+    ///
+    /// - `InvalidTransition`: if the transition is not one of the above.
+    pub fn transition(
+        self,
+        event: TransactionEvent,
+        redisputes: RedisputePolicy,
+    ) -> Result<TransactionStatus, EngineError> {
+        match (self, event) {
+            (TransactionStatus::Ok, TransactionEvent::Dispute) => Ok(TransactionStatus::Disputed),
+            (TransactionStatus::Resolved, TransactionEvent::Dispute)
+                if redisputes == RedisputePolicy::Allow =>
+            {
+                Ok(TransactionStatus::Disputed)
+            }
+            (TransactionStatus::Disputed, TransactionEvent::Resolve) => {
+                Ok(TransactionStatus::Resolved)
+            }
+            (TransactionStatus::Disputed, TransactionEvent::Chargeback) => {
+                Ok(TransactionStatus::Chargeback)
+            }
+            _ => Err(EngineError::InvalidTransition(self, event.target())),
+        }
+    }
+}
+
+impl Display for TransactionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransactionStatus::Ok => f.write_str("ok"),
+            TransactionStatus::Disputed => f.write_str("disputed"),
+            TransactionStatus::Resolved => f.write_str("resolved"),
+            TransactionStatus::Chargeback => f.write_str("chargeback"),
+            TransactionStatus::Declined => f.write_str("declined"),
+        }
+    }
+}
+
+impl FromStr for TransactionStatus {
+    type Err = String;
+
+    /// Parses a status, as printed by `Display`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ok" => Ok(TransactionStatus::Ok),
+            "disputed" => Ok(TransactionStatus::Disputed),
+            "resolved" => Ok(TransactionStatus::Resolved),
+            "chargeback" => Ok(TransactionStatus::Chargeback),
+            "declined" => Ok(TransactionStatus::Declined),
+            _ => Err(format!("{s} is not a valid transaction status.")),
+        }
+    }
+}
+
+impl Display for TransactionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransactionKind::Deposit => f.write_str("deposit"),
+            TransactionKind::Withdrawal => f.write_str("withdrawal"),
+        }
+    }
+}
+
+impl FromStr for TransactionKind {
+    type Err = String;
+
+    /// Parses a kind, as printed by `Display`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deposit" => Ok(TransactionKind::Deposit),
+            "withdrawal" => Ok(TransactionKind::Withdrawal),
+            _ => Err(format!("{s} is not a valid transaction kind.")),
+        }
+    }
+}
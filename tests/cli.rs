@@ -0,0 +1,215 @@
+// runs the command line tool itself, for what only it does: reading several files and standard input, and exiting with a code
+
+use std::{
+    io::Write,
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+};
+
+// writes an input file to the temporary directory, unique to the test and process
+fn input(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "payments-engine-cli-{}-{name}.csv",
+        std::process::id()
+    ));
+    std::fs::write(&path, contents).unwrap();
+
+    path
+}
+
+// runs the tool with `args`, writing `stdin` to its standard input
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+const DEPOSITS: &str = "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,1.0\n";
+const WITHDRAWALS: &str = "type,client,tx,amount\nwithdrawal,1,3,4.0\ndispute,2,2,\n";
+const ACCOUNTS: &str = "client,available,held,total,locked\n\
+                        1,6.0000,0.0000,6.0000,false\n\
+                        2,0.0000,1.0000,1.0000,false\n";
+
+#[test]
+fn files_run_in_order() {
+    let deposits = input("order-deposits", DEPOSITS);
+    let withdrawals = input("order-withdrawals", WITHDRAWALS);
+    let (deposits, withdrawals) = (deposits.to_str().unwrap(), withdrawals.to_str().unwrap());
+
+    // the withdrawal and dispute only apply once the deposits of the first file have been run
+    let output = run(&[deposits, withdrawals], "");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), ACCOUNTS);
+
+    // in the other order, the withdrawal is ignored and the dispute names a transaction not yet made
+    let output = run(&[withdrawals, deposits], "");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "client,available,held,total,locked\n\
+         1,10.0000,0.0000,10.0000,false\n\
+         2,1.0000,0.0000,1.0000,false\n"
+    );
+
+    // transaction ids are shared by every file
+    let output = run(&[deposits, deposits], "");
+    assert_eq!(output.status.code(), Some(4));
+    assert!(stderr(&output).contains(&format!("[{deposits}, line 2, ")));
+}
+
+#[test]
+fn standard_input() {
+    let deposits = input("stdin-deposits", DEPOSITS);
+    let deposits = deposits.to_str().unwrap();
+
+    // `-` reads standard input, in its place among the files
+    let output = run(&[deposits, "-"], WITHDRAWALS);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), ACCOUNTS);
+
+    let output = run(&["-"], DEPOSITS);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(stdout(&output).contains("1,10.0000,0.0000,10.0000,false"));
+
+    // errors in standard input are named after it
+    let output = run(
+        &[deposits, "-"],
+        "type,client,tx,amount\ndeposit,1,5,oops\n",
+    );
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stderr(&output).starts_with("[<stdin>, line 2, byte 22]"));
+}
+
+#[test]
+fn errors_name_their_file() {
+    let valid = input("errors-valid", DEPOSITS);
+    let invalid = input(
+        "errors-invalid",
+        "type,client,tx,amount\ndeposit,3,4,1.0\n\ndeposit,3,5,oops\nwithdrawal,3,6,\n",
+    );
+    let (valid, invalid) = (valid.to_str().unwrap(), invalid.to_str().unwrap());
+
+    // a strict run stops at the first error, naming the file and line it is on
+    let output = run(&[valid, invalid], "");
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(stdout(&output), "");
+    assert!(stderr(&output).starts_with(&format!("[{invalid}, line 4, ")));
+
+    // a lenient run reports every error, and still writes the accounts of the valid rows
+    let output = run(&["--lenient", valid, invalid], "");
+    assert_eq!(output.status.code(), Some(0));
+    let errors = stderr(&output);
+    assert_eq!(errors.lines().count(), 2, "{errors}");
+    assert!(errors
+        .lines()
+        .next()
+        .unwrap()
+        .starts_with(&format!("[{invalid}, line 4, ")));
+    assert!(errors
+        .lines()
+        .nth(1)
+        .unwrap()
+        .starts_with(&format!("[{invalid}, line 5, ")));
+    assert!(stdout(&output).contains("3,1.0000,0.0000,1.0000,false"));
+
+    // a missing file is named too
+    let output = run(&[valid, "missing.csv"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).starts_with("Could not open missing.csv"));
+}
+
+#[test]
+fn snapshots() {
+    let deposits = input("snapshot-deposits", DEPOSITS);
+    let withdrawals = input("snapshot-withdrawals", WITHDRAWALS);
+    let snapshot = std::env::temp_dir().join(format!(
+        "payments-engine-cli-{}.snapshot",
+        std::process::id()
+    ));
+    let (deposits, withdrawals, snapshot) = (
+        deposits.to_str().unwrap(),
+        withdrawals.to_str().unwrap(),
+        snapshot.to_str().unwrap(),
+    );
+
+    // a later run restored from the snapshot of an earlier one ends up with the accounts of a single run
+    let output = run(&["--snapshot", snapshot, deposits], "");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let output = run(&["--restore", snapshot, withdrawals], "");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), ACCOUNTS);
+
+    // a snapshot that cannot be opened exits with the snapshot code
+    let output = run(&["--restore", "missing.snapshot", deposits], "");
+    assert_eq!(output.status.code(), Some(5));
+    assert!(stderr(&output).contains("missing.snapshot"));
+}
+
+#[test]
+fn rejections() {
+    let input = input(
+        "rejections",
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         dispute,1,1,\n\
+         chargeback,1,1,\n\
+         deposit,1,2,5.0\n\
+         deposit,1,3,5.0\n\
+         dispute,1,99,\n\
+         deposit,2,4,oops\n\
+         deposit,2,2,1.0\n",
+    );
+    let log = std::env::temp_dir().join(format!(
+        "payments-engine-cli-{}-rejections.log",
+        std::process::id()
+    ));
+    let (input, log) = (input.to_str().unwrap(), log.to_str().unwrap());
+
+    // ignored commands are logged as they are run, followed by the rows skipped in lenient mode, whether they failed to run or to parse
+    for threads in ["1", "2"] {
+        let output = run(
+            &[
+                "--lenient",
+                "--threads",
+                threads,
+                "--rejections",
+                log,
+                input,
+            ],
+            "",
+        );
+        assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+        assert_eq!(
+            std::fs::read_to_string(log).unwrap(),
+            format!(
+                "file,line,tx,client,reason\n\
+                 {input},7,99,1,unknown transaction\n\
+                 {input},5,2,1,Account Locked: client 1\n\
+                 {input},6,3,1,Account Locked: client 1\n\
+                 {input},8,,,Parse Error: Could not parse amount: invalid digit found in string\n\
+                 {input},9,2,2,Duplicate Transaction: 2\n"
+            )
+        );
+    }
+}